@@ -0,0 +1,284 @@
+// src/batch.rs
+use crate::epub;
+use crate::input;
+use crate::output::OutputFormat;
+use crate::progress::say;
+use clap::ValueEnum;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// File name of the summary written next to the audiobooks
+pub const REPORT_NAME: &str = "batch-report.txt";
+
+/// One book of a batch with the settings that differ from the command line
+#[derive(Clone, Debug, Default)]
+pub struct BatchItem {
+    pub file: PathBuf,
+    /// Read the file in this format instead of going by its extension
+    pub input_format: Option<String>,
+    pub opf: Option<PathBuf>,
+    pub cover: Option<PathBuf>,
+    pub output_format: Option<OutputFormat>,
+    pub name_template: Option<String>,
+    /// Metadata overrides, applied after the ones given on the command line
+    pub metadata: Vec<(String, String)>,
+}
+
+impl BatchItem {
+    fn new(file: PathBuf) -> BatchItem {
+        BatchItem {
+            file,
+            ..BatchItem::default()
+        }
+    }
+
+    /// The book and the OPF and cover that go with it
+    pub fn inputs(&self) -> Vec<&Path> {
+        let mut inputs = vec![self.file.as_path()];
+        inputs.extend(self.opf.as_deref());
+        inputs.extend(self.cover.as_deref());
+        inputs
+    }
+}
+
+/// What happened to one book
+pub enum Outcome {
+    Done(Vec<PathBuf>),
+    Skipped(PathBuf),
+    Failed(String),
+    /// Only estimated by --dry-run, nothing was written
+    DryRun,
+}
+
+/// Books to convert from a folder (searched recursively), a CSV file with a header
+/// row, or a plain list with one path per line. Relative paths in a list are
+/// relative to the list.
+pub fn collect_items(source: &Path) -> Result<Vec<BatchItem>, String> {
+    if source.is_dir() {
+        let mut files = Vec::new();
+        collect_books(source, &mut files)
+            .map_err(|e| format!("Failed to read {}: {}", source.display(), e))?;
+        files.sort();
+        return Ok(files
+            .into_iter()
+            .map(|file| with_bundle(BatchItem::new(file)))
+            .collect());
+    }
+
+    let text = fs::read_to_string(source)
+        .map_err(|e| format!("Failed to read {}: {}", source.display(), e))?;
+    let base = source.parent().unwrap_or(Path::new(""));
+    let is_csv = source
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"));
+    if is_csv {
+        return read_csv(&text, base);
+    }
+
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| with_bundle(BatchItem::new(base.join(line))))
+        .collect())
+}
+
+// EPUBs and every format `input` can read. desc.txt, reader.txt and the report are
+// written by earlier runs, they aren't books.
+fn collect_books(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        if name.starts_with('.') || ["desc.txt", "reader.txt", REPORT_NAME].contains(&name.as_str())
+        {
+            continue;
+        }
+        if path.is_dir() {
+            collect_books(&path, files)?;
+        } else if is_book(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Whether the file is an EPUB or in a format `input` can read
+pub fn is_book(path: &Path) -> bool {
+    epub::is_epub(path) || input::find_format(&path.to_string_lossy(), None).is_some()
+}
+
+// A text book can come with an OPF and a cover image of the same name next to it
+fn with_bundle(mut item: BatchItem) -> BatchItem {
+    let companion = |extensions: &[&str]| {
+        extensions
+            .iter()
+            .map(|extension| item.file.with_extension(extension))
+            .find(|path| path.is_file())
+    };
+    if item.opf.is_none() {
+        item.opf = companion(&["opf"]);
+    }
+    if item.cover.is_none() {
+        item.cover = companion(&["jpg", "jpeg", "png", "webp"]);
+    }
+    item
+}
+
+// Columns: file (required), input_format, opf, cover, output_format, name_template;
+// any other column is a metadata field such as title, author or narrator
+fn read_csv(text: &str, base: &Path) -> Result<Vec<BatchItem>, String> {
+    let mut rows = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(split_csv_line);
+    let header: Vec<String> = rows
+        .next()
+        .ok_or("The CSV file is empty")?
+        .iter()
+        .map(|column| column.trim().to_lowercase())
+        .collect();
+    if !header.iter().any(|column| column == "file") {
+        return Err("The CSV file needs a \"file\" column".to_string());
+    }
+
+    let mut items = Vec::new();
+    for (row_number, row) in rows.enumerate() {
+        let mut item = BatchItem::default();
+        for (column, value) in header.iter().zip(row) {
+            let value = value.trim();
+            if value.is_empty() {
+                continue;
+            }
+            match column.as_str() {
+                "file" => item.file = base.join(value),
+                "input_format" => item.input_format = Some(value.to_string()),
+                "opf" => item.opf = Some(base.join(value)),
+                "cover" => item.cover = Some(base.join(value)),
+                "output_format" | "format" => {
+                    let format = OutputFormat::from_str(value, true).map_err(|_| {
+                        format!("Row {}: unknown output format {}", row_number + 2, value)
+                    })?;
+                    item.output_format = Some(format);
+                }
+                "name_template" | "template" => item.name_template = Some(value.to_string()),
+                field => item.metadata.push((field.to_string(), value.to_string())),
+            }
+        }
+        if item.file.as_os_str().is_empty() {
+            return Err(format!("Row {}: no file given", row_number + 2));
+        }
+        items.push(with_bundle(item));
+    }
+    Ok(items)
+}
+
+// Fields separated by commas; quoted fields may contain commas and "" for a quote
+fn split_csv_line(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// The first of `outputs` that exists and is newer than every input
+pub fn up_to_date(inputs: &[&Path], outputs: &[PathBuf]) -> Option<PathBuf> {
+    let modified = |path: &Path| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let newest_input = inputs
+        .iter()
+        .filter_map(|input| modified(input))
+        .max()
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    outputs
+        .iter()
+        .find(|output| modified(output).is_some_and(|time| time >= newest_input))
+        .cloned()
+}
+
+/// Writes the summary of the batch to `path` and prints the totals
+pub fn write_report(
+    path: &Path,
+    results: &[(BatchItem, Outcome, Duration)],
+) -> std::io::Result<()> {
+    let mut report = String::new();
+    let (mut done, mut skipped, mut failed, mut estimated) = (0, 0, 0, 0);
+    for (item, outcome, elapsed) in results {
+        let file = item.file.display();
+        match outcome {
+            Outcome::Done(outputs) => {
+                done += 1;
+                let outputs: Vec<String> = outputs
+                    .iter()
+                    .map(|output| output.display().to_string())
+                    .collect();
+                report.push_str(&format!(
+                    "DONE     {} -> {} ({})\n",
+                    file,
+                    outputs.join(", "),
+                    format_elapsed(*elapsed)
+                ));
+            }
+            Outcome::Skipped(output) => {
+                skipped += 1;
+                report.push_str(&format!(
+                    "SKIPPED  {} (up to date: {})\n",
+                    file,
+                    output.display()
+                ));
+            }
+            Outcome::Failed(error) => {
+                failed += 1;
+                report.push_str(&format!(
+                    "FAILED   {}: {} ({})\n",
+                    file,
+                    error,
+                    format_elapsed(*elapsed)
+                ));
+            }
+            Outcome::DryRun => {
+                estimated += 1;
+                report.push_str(&format!("DRY RUN  {}\n", file));
+            }
+        }
+    }
+    let mut totals = format!(
+        "{} books: {} converted, {} skipped, {} failed",
+        results.len(),
+        done,
+        skipped,
+        failed
+    );
+    if estimated > 0 {
+        totals.push_str(&format!(", {} only estimated (dry run)", estimated));
+    }
+    report.push_str(&format!("\n{}\n", totals));
+    say!("{}", totals);
+    fs::write(path, report)
+}
+
+fn format_elapsed(elapsed: Duration) -> String {
+    let seconds = elapsed.as_secs();
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
@@ -3,9 +3,9 @@
 use std::fs::File;
 use std::io::{self, BufRead};
 
-pub fn read_sections(file_path: &str) -> Vec<Vec<String>> {
+pub fn read_sections(file_path: &str) -> io::Result<Vec<Vec<String>>> {
     // Open the file in read-only mode
-    let file = File::open(file_path).expect("Failed to open file");
+    let file = File::open(file_path)?;
     let reader = io::BufReader::new(file);
 
     // Create a vector to hold the sections
@@ -14,7 +14,7 @@ pub fn read_sections(file_path: &str) -> Vec<Vec<String>> {
 
     // Read lines from the file
     for line in reader.lines() {
-        let line = line.expect("Failed to read line"); // Unwrap the result
+        let line = line?;
         let line = line.trim().to_string();
 
         // Check if the line starts with "# "
@@ -46,27 +46,28 @@ pub fn read_sections(file_path: &str) -> Vec<Vec<String>> {
         sections.push(current_section);
     }
 
-    sections // Return the vector of sections
+    Ok(sections) // Return the vector of sections
 }
 
-pub fn get_titles(file_path: &str) -> Vec<String> {
+pub fn get_titles(file_path: &str) -> io::Result<Vec<String>> {
     // Open the file in read-only mode
-    let file = File::open(file_path).expect("Failed to open file");
+    let file = File::open(file_path)?;
     let reader = io::BufReader::new(file);
     let mut chapters = Vec::new();
 
     // Read lines from the file
     for line in reader.lines() {
-        let line = line.expect("Failed to read line"); // Unwrap the result
+        let line = line?;
         // Check if the line starts with "# "
         if line.starts_with("# ") {
             chapters.push(line.replace("# ", "").trim().to_string());
         }
     }
-    chapters
+    Ok(chapters)
 }
 
 pub struct Book {
+    title: Option<String>,
     chapters: Vec<(String, Vec<String>)>,
 }
 
@@ -74,14 +75,28 @@ impl Book {
     // Function to create a new, empty book
     pub fn new() -> Self {
         Book {
+            title: None,
             chapters: Vec::new(),
         }
     }
 
+    // Method to set the title found in the document itself
+    pub fn set_title(&mut self, title: &str) {
+        self.title = Some(title.to_string());
+    }
+    // Method to get the title found in the document, if any
+    pub fn get_title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
     // Method to add a chapter with multiple sections or paragraphs
     pub fn add_chapter(&mut self, title: &str, content: Vec<String>) {
         self.chapters.push((title.to_string(), content));
     }
+    // Method to get the chapter titles in order
+    pub fn get_titles(&self) -> Vec<String> {
+        self.chapters.iter().map(|(title, _)| title.clone()).collect()
+    }
     // Method to get all chapters
     pub fn get_all_chapters(&self) -> Vec<(&String, &Vec<String>)> {
         self.chapters
@@ -89,4 +104,17 @@ impl Book {
             .map(|(title, content)| (title, content))
             .collect()
     }
+    // Method to get a copy with only the given chapters (numbered from 1)
+    pub fn with_chapters(&self, numbers: &[usize]) -> Book {
+        Book {
+            title: self.title.clone(),
+            chapters: self
+                .chapters
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| numbers.contains(&(i + 1)))
+                .map(|(_, chapter)| chapter.clone())
+                .collect(),
+        }
+    }
 }
\ No newline at end of file
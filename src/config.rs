@@ -0,0 +1,240 @@
+// src/config.rs
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the config file, in the current folder and in the user's config folder
+pub const FILE_NAME: &str = "edgeab.toml";
+
+/// One setting from a config file
+pub struct Setting {
+    /// The command line option without the dashes, e.g. name_template
+    pub key: String,
+    pub value: toml::Value,
+    /// The file it came from
+    pub origin: PathBuf,
+}
+
+/// The settings of the config files in the order they apply: the top-level keys of
+/// the user-wide file, then of the project-local one, then the chosen profile of each.
+/// Later settings of the same key win, the command line beats them all.
+#[derive(Default)]
+pub struct Config {
+    /// The files that were found
+    pub files: Vec<PathBuf>,
+    pub profile: Option<String>,
+    pub settings: Vec<Setting>,
+}
+
+impl Config {
+    /// The setting that applies for `key`, if any file has it
+    pub fn get(&self, key: &str) -> Option<&Setting> {
+        self.settings
+            .iter()
+            .rev()
+            .find(|setting| setting.key == key)
+    }
+}
+
+/// ~/.config/edgeab/edgeab.toml, or the same in $XDG_CONFIG_HOME or %APPDATA%
+pub fn user_path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(dir.join("edgeab").join(FILE_NAME))
+}
+
+/// Reads the user-wide and the project-local config file. The profile is the one
+/// asked for, or else the one the files name with `profile = "..."`.
+pub fn load(profile: Option<&str>) -> Result<Config, String> {
+    let paths = user_path()
+        .into_iter()
+        .chain([PathBuf::from(FILE_NAME)])
+        .filter(|path| path.is_file());
+    let mut tables = Vec::new();
+    for path in paths {
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let table: toml::Table =
+            toml::from_str(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        tables.push((path, table));
+    }
+
+    let mut config = Config {
+        files: tables.iter().map(|(path, _)| path.clone()).collect(),
+        profile: profile.map(str::to_string),
+        ..Config::default()
+    };
+    // The project's default profile beats the user's
+    if config.profile.is_none() {
+        for (path, table) in &tables {
+            match table.get("profile") {
+                Some(toml::Value::String(name)) => config.profile = Some(name.clone()),
+                Some(_) => return Err(format!("{}: profile must be a string", path.display())),
+                None => {}
+            }
+        }
+    }
+    let profile_name = config.profile.clone();
+
+    let mut add = |table: &toml::Table, path: &PathBuf| {
+        for (key, value) in table {
+            if key == "profile" || key == "profiles" {
+                continue;
+            }
+            config.settings.push(Setting {
+                key: key.replace('-', "_"),
+                value: value.clone(),
+                origin: path.clone(),
+            });
+        }
+    };
+    for (path, table) in &tables {
+        add(table, path);
+    }
+    // The chosen profile beats a default of the project file
+    let mut found = false;
+    if let Some(name) = &profile_name {
+        for (path, table) in &tables {
+            let Some(profile) = table
+                .get("profiles")
+                .and_then(|profiles| profiles.get(name))
+            else {
+                continue;
+            };
+            let toml::Value::Table(profile) = profile else {
+                return Err(format!(
+                    "{}: profiles.{} must be a table",
+                    path.display(),
+                    name
+                ));
+            };
+            add(profile, path);
+            found = true;
+        }
+    }
+    if let Some(name) = profile_name.filter(|_| !found) {
+        return Err(format!(
+            "No profile \"{}\" in {}",
+            name,
+            describe_files(&config.files)
+        ));
+    }
+    Ok(config)
+}
+
+fn describe_files(files: &[PathBuf]) -> String {
+    if files.is_empty() {
+        return format!("any {} (none was found)", FILE_NAME);
+    }
+    files
+        .iter()
+        .map(|file| file.display().to_string())
+        .collect::<Vec<_>>()
+        .join(" or ")
+}
+
+impl Setting {
+    fn error(&self, expected: &str) -> String {
+        format!(
+            "{}: {} must be {}",
+            self.origin.display(),
+            self.key,
+            expected
+        )
+    }
+
+    /// A string, or a whole number written without quotes (bitrate = 64000)
+    pub fn string(&self) -> Result<String, String> {
+        match &self.value {
+            toml::Value::String(text) => Ok(text.clone()),
+            toml::Value::Integer(number) => Ok(number.to_string()),
+            _ => Err(self.error("a string")),
+        }
+    }
+
+    /// A number from `min` to `max`
+    pub fn number(&self, min: f64, max: f64) -> Result<f64, String> {
+        let number = match &self.value {
+            toml::Value::Integer(number) => Some(*number as f64),
+            toml::Value::Float(number) => Some(*number),
+            _ => None,
+        };
+        number
+            .filter(|number| (min..=max).contains(number))
+            .ok_or_else(|| self.error(&format!("a number from {} to {}", min, max)))
+    }
+
+    /// A whole number from `min` to `max`
+    pub fn whole_number(&self, min: u32, max: u32) -> Result<u32, String> {
+        self.value
+            .as_integer()
+            .and_then(|number| u32::try_from(number).ok())
+            .filter(|number| (min..=max).contains(number))
+            .ok_or_else(|| self.error(&format!("a whole number from {} to {}", min, max)))
+    }
+
+    pub fn flag(&self) -> Result<bool, String> {
+        self.value
+            .as_bool()
+            .ok_or_else(|| self.error("true or false"))
+    }
+
+    /// An array of strings, or a single string
+    pub fn strings(&self) -> Result<Vec<String>, String> {
+        match &self.value {
+            toml::Value::Array(items) => items
+                .iter()
+                .map(|item| item.as_str().map(str::to_string))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| self.error("a list of strings")),
+            _ => self.string().map(|text| vec![text]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setting(value: &str) -> Setting {
+        let table: toml::Table = toml::from_str(&format!("value = {}", value)).unwrap();
+        Setting {
+            key: "pause".to_string(),
+            value: table["value"].clone(),
+            origin: PathBuf::from("edgeab.toml"),
+        }
+    }
+
+    #[test]
+    fn numbers_out_of_range_name_their_file() {
+        assert_eq!(setting("1").number(0.0, 60.0), Ok(1.0));
+        assert_eq!(setting("0.5").number(0.0, 60.0), Ok(0.5));
+        for value in ["-1", "61", "nan", "inf", "\"1\""] {
+            assert_eq!(
+                setting(value).number(0.0, 60.0),
+                Err("edgeab.toml: pause must be a number from 0 to 60".to_string())
+            );
+        }
+
+        assert_eq!(setting("44100").whole_number(8000, 192_000), Ok(44100));
+        for value in ["44100.0", "1.5", "-2", "4294967297", "0"] {
+            assert_eq!(
+                setting(value).whole_number(8000, 192_000),
+                Err("edgeab.toml: pause must be a whole number from 8000 to 192000".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn strings_take_whole_numbers_but_not_fractions() {
+        assert_eq!(setting("\"64k\"").string(), Ok("64k".to_string()));
+        assert_eq!(setting("64000").string(), Ok("64000".to_string()));
+        assert!(setting("1.5").string().is_err());
+        assert_eq!(
+            setting("[\"a\", \"b\"]").strings(),
+            Ok(vec!["a".to_string(), "b".to_string()])
+        );
+    }
+}
@@ -0,0 +1,419 @@
+mod font;
+
+use clap::ValueEnum;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat, Rgb, RgbImage};
+use mp4ameta::{Img, ImgFmt, Tag};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// How a non-square cover is made square
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CoverMode {
+    /// Crop the centre of the image
+    Crop,
+    /// Letterbox the image on a black square
+    Pad,
+    /// Keep the original aspect ratio
+    Keep,
+}
+
+// Cover processing options, shared by the build and tag commands
+#[derive(clap::Args, Clone, Debug)]
+pub struct CoverSettings {
+    /// How to fit a non-square cover
+    #[arg(long = "cover-mode", value_enum, default_value_t = CoverMode::Crop)]
+    pub mode: CoverMode,
+
+    /// Scale the cover down so neither side exceeds this many pixels
+    #[arg(long = "cover-size")]
+    pub max_size: Option<u32>,
+
+    /// JPEG quality (1-100) used when re-encoding the cover
+    #[arg(long = "cover-quality", default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub jpeg_quality: u8,
+}
+
+/// A processed cover written to disk, along with the format it was encoded in
+pub struct Cover {
+    pub path: String,
+    pub format: ImageFormat,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Cover {
+    pub fn mime_type(&self) -> &'static str {
+        match self.format {
+            ImageFormat::Png => "image/png",
+            _ => "image/jpeg",
+        }
+    }
+
+    fn mp4_format(&self) -> ImgFmt {
+        match self.format {
+            ImageFormat::Png => ImgFmt::Png,
+            _ => ImgFmt::Jpeg,
+        }
+    }
+}
+
+/// Loads the cover image, fits and scales it according to the settings and writes it
+/// to `dir` as bcover.jpg (or bcover.png if it has transparency). `dir` is a temp folder
+/// of the run, the caller removes the file when it's done with it.
+pub fn prepare_cover(
+    image_path: &str,
+    settings: &CoverSettings,
+    dir: &Path,
+) -> Result<Cover, String> {
+    let img = image::open(image_path)
+        .map_err(|e| format!("Failed to open cover image {}: {}", image_path, e))?;
+
+    let img = match settings.mode {
+        CoverMode::Crop => center_crop(&img),
+        CoverMode::Pad => letterbox(&img),
+        CoverMode::Keep => img,
+    };
+    let img = match settings.max_size {
+        Some(max) if img.width() > max || img.height() > max => {
+            img.resize(max, max, FilterType::Lanczos3)
+        }
+        _ => img,
+    };
+
+    // JPEG has no alpha channel, so transparent covers stay PNG
+    let format = if img.color().has_alpha() {
+        ImageFormat::Png
+    } else {
+        ImageFormat::Jpeg
+    };
+    let name = match format {
+        ImageFormat::Png => "bcover.png",
+        _ => "bcover.jpg",
+    };
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    let path = dir.join(name).to_string_lossy().to_string();
+
+    let written = match format {
+        ImageFormat::Png => img.save_with_format(&path, ImageFormat::Png),
+        _ => File::create(&path)
+            .map_err(image::ImageError::IoError)
+            .and_then(|file| {
+                let encoder =
+                    JpegEncoder::new_with_quality(BufWriter::new(file), settings.jpeg_quality);
+                DynamicImage::ImageRgb8(img.to_rgb8()).write_with_encoder(encoder)
+            }),
+    };
+    written.map_err(|e| format!("Failed to write cover image {}: {}", path, e))?;
+
+    Ok(Cover {
+        path,
+        format,
+        width: img.width(),
+        height: img.height(),
+    })
+}
+
+// Largest square around the centre of the image
+fn center_crop(img: &DynamicImage) -> DynamicImage {
+    let size = img.width().min(img.height());
+    let x = (img.width() - size) / 2;
+    let y = (img.height() - size) / 2;
+    img.crop_imm(x, y, size, size)
+}
+
+// The whole image centred on a black square
+fn letterbox(img: &DynamicImage) -> DynamicImage {
+    let size = img.width().max(img.height());
+    let mut canvas = RgbImage::from_pixel(size, size, Rgb([0, 0, 0]));
+    let x = (size - img.width()) / 2;
+    let y = (size - img.height()) / 2;
+    image::imageops::overlay(&mut canvas, &img.to_rgb8(), x as i64, y as i64);
+    DynamicImage::ImageRgb8(canvas)
+}
+
+/// Sets the cover as the artwork of an MP4 (m4b/m4a) file
+pub fn add_cover_to_mp4(mp4_path: &str, cover: &Cover) -> Result<(), String> {
+    let mut tag = Tag::read_from_path(mp4_path)
+        .map_err(|e| format!("Failed to read tags from {}: {}", mp4_path, e))?;
+    let image_data =
+        fs::read(&cover.path).map_err(|e| format!("Failed to read {}: {}", cover.path, e))?;
+
+    tag.set_artwork(Img::new(cover.mp4_format(), image_data));
+    tag.write_to_path(mp4_path)
+        .map_err(|e| format!("Failed to write cover to {}: {}", mp4_path, e))
+}
+
+/// Layout of the cover generated for books that don't come with one
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CoverTemplate {
+    /// Title on a coloured band across the middle
+    Band,
+    /// Title and author on a plain background
+    Plain,
+    /// Title inside a thin frame
+    Frame,
+    /// Don't generate a cover
+    None,
+}
+
+const GENERATED_SIZE: u32 = 1600;
+const MARGIN: u32 = 160;
+
+// Background, accent and text colours; the title picks one so books look different
+const PALETTES: [[[u8; 3]; 3]; 4] = [
+    [[28, 42, 74], [214, 170, 90], [245, 240, 228]],
+    [[34, 68, 52], [230, 200, 120], [240, 240, 230]],
+    [[96, 28, 40], [235, 205, 160], [250, 244, 235]],
+    [[48, 52, 60], [120, 180, 200], [240, 240, 240]],
+];
+
+/// Renders a square cover with the title and author and saves it as PNG. A title or
+/// author in a script the font doesn't have is left off rather than drawn wrong.
+pub fn generate_cover(
+    title: &str,
+    author: Option<&str>,
+    template: CoverTemplate,
+    path: &str,
+) -> Result<(), String> {
+    let palette = PALETTES[title.bytes().map(|b| b as usize).sum::<usize>() % PALETTES.len()];
+    let [background, accent, text] = palette.map(Rgb);
+    let mut img = RgbImage::from_pixel(GENERATED_SIZE, GENERATED_SIZE, background);
+    let width = GENERATED_SIZE - 2 * MARGIN;
+
+    // The band and frame still make it a cover without the title
+    let title = if font::can_draw(title) { title } else { "" };
+    let (title_scale, title_lines) = fit_text(title, width, 5, 20, 6);
+    let title_height = text_height(title_lines.len(), title_scale);
+    let title_top = (GENERATED_SIZE * 2 / 5).saturating_sub(title_height / 2);
+
+    let title_color = match template {
+        CoverTemplate::Band => {
+            let padding = 4 * title_scale;
+            fill_rect(
+                &mut img,
+                0,
+                title_top.saturating_sub(padding),
+                GENERATED_SIZE,
+                title_height + 2 * padding,
+                accent,
+            );
+            background
+        }
+        CoverTemplate::Frame => {
+            let inset = MARGIN / 2;
+            let side = GENERATED_SIZE - 2 * inset;
+            let thickness = 12;
+            fill_rect(&mut img, inset, inset, side, thickness, accent);
+            fill_rect(
+                &mut img,
+                inset,
+                inset + side - thickness,
+                side,
+                thickness,
+                accent,
+            );
+            fill_rect(&mut img, inset, inset, thickness, side, accent);
+            fill_rect(
+                &mut img,
+                inset + side - thickness,
+                inset,
+                thickness,
+                side,
+                accent,
+            );
+            text
+        }
+        CoverTemplate::Plain | CoverTemplate::None => text,
+    };
+    draw_lines(&mut img, &title_lines, title_scale, title_top, title_color);
+
+    if let Some(author) =
+        author.filter(|author| !author.trim().is_empty() && font::can_draw(author))
+    {
+        let (author_scale, author_lines) = fit_text(author, width, 2, 8, 4);
+        let author_top = GENERATED_SIZE - MARGIN - text_height(author_lines.len(), author_scale);
+        let author_color = match template {
+            CoverTemplate::Band => text,
+            _ => accent,
+        };
+        draw_lines(
+            &mut img,
+            &author_lines,
+            author_scale,
+            author_top,
+            author_color,
+        );
+    }
+
+    img.save_with_format(path, ImageFormat::Png)
+        .map_err(|e| format!("Failed to write generated cover {}: {}", path, e))
+}
+
+// Largest scale at which the text wraps into at most max_lines lines
+fn fit_text(
+    text: &str,
+    width: u32,
+    max_lines: usize,
+    max_scale: u32,
+    min_scale: u32,
+) -> (u32, Vec<String>) {
+    for scale in (min_scale..=max_scale).rev() {
+        let max_chars = (width / ((font::WIDTH + 1) * scale)) as usize;
+        let lines = wrap(text, max_chars);
+        // Only break up words once the smallest size is reached
+        let words_fit = text
+            .split_whitespace()
+            .all(|word| word.chars().count() <= max_chars);
+        if lines.len() <= max_lines && words_fit {
+            return (scale, lines);
+        }
+    }
+    let mut lines = wrap(text, (width / ((font::WIDTH + 1) * min_scale)) as usize);
+    lines.truncate(max_lines);
+    (min_scale, lines)
+}
+
+// Greedy word wrap; words longer than a line are broken up
+fn wrap(text: &str, max_chars: usize) -> Vec<String> {
+    let max_chars = max_chars.max(1);
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let word: Vec<char> = word.chars().collect();
+        for piece in word.chunks(max_chars) {
+            let piece: String = piece.iter().collect();
+            let line_len = line.chars().count();
+            if line_len > 0 && line_len + 1 + piece.chars().count() > max_chars {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&piece);
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+fn text_height(lines: usize, scale: u32) -> u32 {
+    let line_height = (font::HEIGHT + 2) * scale;
+    (lines as u32 * line_height).saturating_sub(2 * scale)
+}
+
+// Draws each line horizontally centred, starting at top
+fn draw_lines(img: &mut RgbImage, lines: &[String], scale: u32, top: u32, color: Rgb<u8>) {
+    for (row, line) in lines.iter().enumerate() {
+        let chars = line.chars().count() as u32;
+        let line_width = (chars * (font::WIDTH + 1)).saturating_sub(1) * scale;
+        let mut x = img.width().saturating_sub(line_width) / 2;
+        let y = top + row as u32 * (font::HEIGHT + 2) * scale;
+        for c in line.chars() {
+            let glyph = font::glyph(c).unwrap_or([0x00; 7]);
+            for (dy, bits) in glyph.iter().enumerate() {
+                for dx in 0..font::WIDTH {
+                    if bits & (1 << (font::WIDTH - 1 - dx)) != 0 {
+                        let px = x + dx * scale;
+                        let py = y + dy as u32 * scale;
+                        fill_rect(img, px, py, scale, scale, color);
+                    }
+                }
+            }
+            x += (font::WIDTH + 1) * scale;
+        }
+    }
+}
+
+fn fill_rect(img: &mut RgbImage, x: u32, y: u32, width: u32, height: u32, color: Rgb<u8>) {
+    for py in y..(y + height).min(img.height()) {
+        for px in x..(x + width).min(img.width()) {
+            img.put_pixel(px, py, color);
+        }
+    }
+}
+
+// Ogg has no picture stream, players read the cover from a base64 encoded FLAC
+// picture block in the METADATA_BLOCK_PICTURE comment
+pub fn write_picture_metadata(cover: &Cover, output_path: &str) -> io::Result<()> {
+    let data = fs::read(&cover.path)?;
+    let mime = cover.mime_type().as_bytes();
+
+    let mut block = Vec::new();
+    block.extend(3u32.to_be_bytes()); // Picture type: front cover
+    block.extend((mime.len() as u32).to_be_bytes());
+    block.extend(mime);
+    block.extend(0u32.to_be_bytes()); // No description
+    block.extend(cover.width.to_be_bytes());
+    block.extend(cover.height.to_be_bytes());
+    block.extend(24u32.to_be_bytes()); // Color depth
+    block.extend(0u32.to_be_bytes()); // Not an indexed image
+    block.extend((data.len() as u32).to_be_bytes());
+    block.extend(data);
+
+    // '=' has to be escaped in FFmpeg metadata files
+    let encoded = base64_encode(&block).replace('=', "\\=");
+    let mut file = File::create(output_path)?;
+    writeln!(file, ";FFMETADATA1")?;
+    writeln!(file, "METADATA_BLOCK_PICTURE={}", encoded)?;
+    Ok(())
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        encoded.push(ALPHABET[(n >> 18) as usize & 63] as char);
+        encoded.push(ALPHABET[(n >> 12) as usize & 63] as char);
+        if chunk.len() > 1 {
+            encoded.push(ALPHABET[(n >> 6) as usize & 63] as char);
+        } else {
+            encoded.push('=');
+        }
+        if chunk.len() > 2 {
+            encoded.push(ALPHABET[n as usize & 63] as char);
+        } else {
+            encoded.push('=');
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pixels of the generated cover that aren't the background
+    fn drawn_pixels(title: &str, author: Option<&str>) -> usize {
+        let dir = std::env::temp_dir().join(format!("edgeab-cover-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{}.png", title.len()));
+        let path = path.to_string_lossy();
+        generate_cover(title, author, CoverTemplate::Plain, &path).unwrap();
+        let img = image::open(path.as_ref()).unwrap().to_rgb8();
+        let background = *img.get_pixel(0, 0);
+        img.pixels().filter(|pixel| **pixel != background).count()
+    }
+
+    #[test]
+    fn text_the_font_lacks_is_left_off() {
+        assert!(font::can_draw("Les Misérables: Part 1 (1862)"));
+        assert!(font::can_draw("Tom + Jerry"));
+        assert!(!font::can_draw("Война и мир"));
+        assert!(!font::can_draw("Emma 三"));
+
+        assert!(drawn_pixels("Emma", None) > 0);
+        assert_eq!(drawn_pixels("Война и мир", Some("Лев Толстой")), 0);
+    }
+}
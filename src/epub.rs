@@ -1,13 +1,16 @@
+use crate::metdata;
 use rbook;
 use rbook::read::ContentType;
 use rbook::Ebook;
 use scraper::{Html, Selector};
-use std::fs::File;
-use std::io::{self, Write};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use xmltree::Element;
 
 /// Function to extract chapter previews from an EPUB file and write them to an output file.
 /// Filters out chapters with titles containing unwanted phrases.
-pub fn make_file(input_epub: &str, output_path: &str) -> io::Result<()> {
+pub fn make_file(input_epub: &str, output_path: &str, skip_chapters: &[String]) -> io::Result<()> {
     // Creating an epub instance
     let epub = match rbook::Epub::new(input_epub) {
         Ok(epub) => epub,
@@ -37,8 +40,13 @@ pub fn make_file(input_epub: &str, output_path: &str) -> io::Result<()> {
         }
     };
 
-    // Define phrases to filter out
-    let filter_phrases = vec!["copyright", "landmarks", "table of contents"];
+    // Define phrases to filter out, plus the ones asked for
+    let mut filter_phrases = vec!["copyright", "landmarks", "table of contents"];
+    let skip_chapters: Vec<String> = skip_chapters
+        .iter()
+        .map(|phrase| phrase.to_lowercase())
+        .collect();
+    filter_phrases.extend(skip_chapters.iter().map(String::as_str));
 
     // Function to check if a title should be filtered out
     fn should_filter(title: &str, filter_phrases: &[&str]) -> bool {
@@ -137,3 +145,84 @@ pub fn make_file(input_epub: &str, output_path: &str) -> io::Result<()> {
 
     Ok(())
 }
+
+/// Copies the cover image named in the EPUB's OPF manifest to `cover.<ext>` in
+/// `output_dir`. Returns the written path, or None if the book has no cover.
+pub fn extract_cover(input_epub: &str, output_dir: &Path) -> io::Result<Option<String>> {
+    let invalid = |e: String| io::Error::new(io::ErrorKind::InvalidData, e);
+    let mut archive = zip::ZipArchive::new(BufReader::new(File::open(input_epub)?))
+        .map_err(|e| invalid(e.to_string()))?;
+
+    let opf_path = opf_path(&mut archive)?;
+    let opf = read_entry(&mut archive, &opf_path)?;
+    let package = Element::parse(opf.as_slice()).map_err(|e| invalid(e.to_string()))?;
+    let Some(href) = metdata::cover_href(&package) else {
+        return Ok(None);
+    };
+
+    let entry_name = resolve_href(&opf_path, &href);
+    let data = read_entry(&mut archive, &entry_name)?;
+
+    let extension = Path::new(&entry_name)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_else(|| "jpg".to_string());
+    let cover_path = output_dir.join(format!("cover.{}", extension));
+    fs::write(&cover_path, data)?;
+    Ok(Some(cover_path.to_string_lossy().to_string()))
+}
+
+/// Whether the file is an EPUB by its extension
+pub fn is_epub(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("epub"))
+}
+
+/// Writes the EPUB's OPF package document to `output_path`
+pub fn extract_opf(input_epub: &str, output_path: &str) -> io::Result<()> {
+    let mut archive = zip::ZipArchive::new(BufReader::new(File::open(input_epub)?))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    let opf_path = opf_path(&mut archive)?;
+    fs::write(output_path, read_entry(&mut archive, &opf_path)?)
+}
+
+/// Location of the OPF package document, from META-INF/container.xml
+pub fn opf_path<R: Read + io::Seek>(archive: &mut zip::ZipArchive<R>) -> io::Result<String> {
+    let container = read_entry(archive, "META-INF/container.xml")?;
+    let container = Element::parse(container.as_slice())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    container
+        .get_child("rootfiles")
+        .and_then(|rootfiles| rootfiles.get_child("rootfile"))
+        .and_then(|rootfile| rootfile.attributes.get("full-path"))
+        .cloned()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "EPUB container has no rootfile"))
+}
+
+/// Archive path of a manifest href, which is relative to the document it appears in
+pub fn resolve_href(base: &str, href: &str) -> String {
+    let mut parts: Vec<&str> = base.split('/').collect();
+    parts.pop();
+    for segment in href.split('#').next().unwrap_or("").split('/') {
+        match segment {
+            ".." => {
+                parts.pop();
+            }
+            "." | "" => {}
+            segment => parts.push(segment),
+        }
+    }
+    parts.join("/").replace("%20", " ")
+}
+
+pub fn read_entry<R: Read + io::Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    name: &str,
+) -> io::Result<Vec<u8>> {
+    let mut entry = archive
+        .by_name(name)
+        .map_err(|e| io::Error::new(io::ErrorKind::NotFound, format!("{}: {}", name, e)))?;
+    let mut data = Vec::new();
+    entry.read_to_end(&mut data)?;
+    Ok(data)
+}
@@ -0,0 +1,182 @@
+// src/estimate.rs
+use crate::book::Book;
+use crate::progress::say;
+use crate::tts::Voice;
+use colored::*;
+
+// Words per minute of the neural voices at medium rate, they read a bit slower than
+// people talk
+const WORDS_PER_MINUTE: f64 = 150.0;
+// Languages written without spaces are counted in characters instead
+const CHARACTERS_PER_MINUTE: f64 = 300.0;
+
+// Chapters shorter than this are usually a heading or a copyright page split off on
+// their own
+const SHORT_CHAPTER_CHARACTERS: usize = 500;
+// Chapters this many times longer than the median probably swallowed the next chapters
+const LONG_CHAPTER_FACTOR: usize = 4;
+
+/// What a chapter is expected to cost and take
+pub struct ChapterEstimate {
+    pub title: String,
+    pub paragraphs: usize,
+    pub characters: usize,
+    pub words: usize,
+    /// Seconds of audio, with the pauses between paragraphs
+    pub seconds: f64,
+    pub warning: Option<String>,
+}
+
+/// Estimates every chapter of the book for the voice, with `pause` seconds between
+/// paragraphs, without synthesizing anything
+pub fn estimate_book(book: &Book, voice: &Voice, pause: f64) -> Vec<ChapterEstimate> {
+    let per_second = speaking_rate(voice) / 60.0;
+    let by_characters = counts_characters(&voice.name);
+    let mut estimates: Vec<ChapterEstimate> = book
+        .get_all_chapters()
+        .into_iter()
+        .map(|(title, content)| {
+            let characters = content.iter().map(|text| text.chars().count()).sum();
+            let words = content
+                .iter()
+                .map(|text| text.split_whitespace().count())
+                .sum();
+            let units = if by_characters { characters } else { words };
+            let pauses = content.len().saturating_sub(1) as f64 * pause;
+            ChapterEstimate {
+                title: title.clone(),
+                paragraphs: content.len(),
+                characters,
+                words,
+                seconds: units as f64 / per_second + pauses,
+                warning: None,
+            }
+        })
+        .collect();
+
+    let mut lengths: Vec<usize> = estimates.iter().map(|chapter| chapter.characters).collect();
+    lengths.sort();
+    let median = lengths.get(lengths.len() / 2).copied().unwrap_or(0);
+    for chapter in &mut estimates {
+        chapter.warning = if chapter.paragraphs < 2 {
+            // read_chapter leaves these out
+            Some("fewer than 2 paragraphs, it will be skipped".to_string())
+        } else if chapter.characters < SHORT_CHAPTER_CHARACTERS {
+            Some(format!("short, {} characters", chapter.characters))
+        } else if median > 0 && chapter.characters > median * LONG_CHAPTER_FACTOR {
+            Some(format!(
+                "long, {} times the median chapter",
+                chapter.characters / median
+            ))
+        } else {
+            None
+        };
+    }
+    estimates
+}
+
+/// Prints the table of chapters and the totals of a dry run
+pub fn print_estimates(estimates: &[ChapterEstimate], voice: &Voice) {
+    say!(
+        "{}",
+        format!("Dry run with {} at rate {}", voice.name, voice.rate).yellow()
+    );
+    say!(
+        "{:>4}  {:>10}  {:>10}  {:>8}  {:>9}  Title",
+        "#",
+        "Paragraphs",
+        "Characters",
+        "Words",
+        "Duration"
+    );
+    for (number, chapter) in estimates.iter().enumerate() {
+        let line = format!(
+            "{:>4}  {:>10}  {:>10}  {:>8}  {:>9}  {}",
+            number + 1,
+            chapter.paragraphs,
+            chapter.characters,
+            chapter.words,
+            format_duration(chapter.seconds),
+            chapter.title
+        );
+        match &chapter.warning {
+            Some(warning) => say!("{}  {}", line.yellow(), format!("({})", warning).yellow()),
+            None => say!("{}", line),
+        }
+    }
+
+    // Every paragraph is one request to the engine
+    let requests: usize = estimates
+        .iter()
+        .filter(|chapter| chapter.paragraphs >= 2)
+        .map(|chapter| chapter.paragraphs)
+        .sum();
+    let characters: usize = estimates.iter().map(|chapter| chapter.characters).sum();
+    let seconds: f64 = estimates
+        .iter()
+        .filter(|chapter| chapter.paragraphs >= 2)
+        .map(|chapter| chapter.seconds)
+        .sum();
+    say!(
+        "{}",
+        format!(
+            "{} chapters, {} requests, {} characters, about {} of audio",
+            estimates.len(),
+            requests,
+            characters,
+            format_duration(seconds)
+        )
+        .green()
+    );
+}
+
+/// Estimated seconds the voice takes to read the text
+pub fn speaking_seconds(text: &str, voice: &Voice) -> f64 {
+    let units = if counts_characters(&voice.name) {
+        text.chars().count()
+    } else {
+        text.split_whitespace().count()
+    };
+    units as f64 / speaking_rate(voice) * 60.0
+}
+
+// Words (or characters) per minute at the voice's rate. The rate is what edge-tts
+// takes: a keyword or a relative change such as "+10%".
+fn speaking_rate(voice: &Voice) -> f64 {
+    let base = if counts_characters(&voice.name) {
+        CHARACTERS_PER_MINUTE
+    } else {
+        WORDS_PER_MINUTE
+    };
+    let rate = voice.rate.trim();
+    let factor = match rate {
+        "x-slow" => 0.5,
+        "slow" => 0.75,
+        "fast" => 1.25,
+        "x-fast" => 1.75,
+        _ => rate
+            .strip_suffix('%')
+            .and_then(|percent| percent.parse::<f64>().ok())
+            .map(|percent| 1.0 + percent / 100.0)
+            .unwrap_or(1.0),
+    };
+    base * factor.max(0.1)
+}
+
+// Chinese and Japanese voices, whose text has no spaces between words
+fn counts_characters(voice: &str) -> bool {
+    ["zh-", "ja-", "yue-", "wuu-"]
+        .iter()
+        .any(|prefix| voice.starts_with(prefix))
+}
+
+/// h:mm:ss
+pub fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    format!(
+        "{}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
@@ -0,0 +1,397 @@
+// src/feed.rs
+use crate::cover::Cover;
+use crate::ffmpeg;
+use crate::metdata::{html_to_text, BookMetadata};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const AUDIO_EXTENSIONS: [&str; 5] = ["m4b", "m4a", "mp3", "opus", "ogg"];
+
+/// One audio file of the feed
+struct Episode {
+    path: PathBuf,
+    /// Path relative to the feed directory, with '/' separators
+    relative: String,
+    title: String,
+    description: Option<String>,
+    duration: f64,
+    size: u64,
+    published: u64,
+    chapters: Vec<(String, f64, f64)>,
+}
+
+/// Writes an RSS 2.0 podcast feed for every audiobook or chapter file below `dir`,
+/// served from `base_url`. Files are published in name order, so numbered chapters
+/// and " - Part N" files play in sequence. Returns the number of episodes.
+pub fn write_feed(
+    dir: &Path,
+    base_url: &str,
+    output: &Path,
+    channel: &BookMetadata,
+    cover: Option<&Cover>,
+) -> Result<usize, String> {
+    let files = audio_files(dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+    if files.is_empty() {
+        return Err(format!("No audio files found in {}", dir.display()));
+    }
+
+    let mut episodes: Vec<Episode> = Vec::new();
+    for path in files {
+        let episode = read_episode(dir, &path)?;
+        // Apps order episodes by date, so never let a later file come out older
+        let published = match episodes.last() {
+            Some(previous) => episode.published.max(previous.published + 60),
+            None => episode.published,
+        };
+        episodes.push(Episode {
+            published,
+            ..episode
+        });
+    }
+
+    let base_url = base_url.trim_end_matches('/');
+    let artwork = match cover {
+        Some(cover) => Some(copy_artwork(dir, cover)?),
+        None => find_artwork(dir),
+    };
+
+    let author = channel.authors.join(" & ");
+    let description = channel
+        .description
+        .as_deref()
+        .map(html_to_text)
+        .unwrap_or_else(|| channel.full_title());
+
+    let mut rss = String::new();
+    rss.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    rss.push_str(concat!(
+        "<rss version=\"2.0\" xmlns:itunes=\"http://www.itunes.com/dtds/podcast-1.0.dtd\" ",
+        "xmlns:podcast=\"https://podcastindex.org/namespace/1.0\">\n"
+    ));
+    rss.push_str("  <channel>\n");
+    push_element(&mut rss, 4, "title", &channel.full_title());
+    push_element(&mut rss, 4, "link", &format!("{}/", base_url));
+    push_element(&mut rss, 4, "description", &description);
+    if let Some(language) = &channel.language {
+        push_element(&mut rss, 4, "language", language);
+    }
+    if !author.is_empty() {
+        push_element(&mut rss, 4, "itunes:author", &author);
+    }
+    push_element(&mut rss, 4, "itunes:summary", &description);
+    push_element(&mut rss, 4, "itunes:type", "serial");
+    push_element(&mut rss, 4, "itunes:explicit", "false");
+    // The feed is meant for a closed audience, keep it out of the directories
+    push_element(&mut rss, 4, "itunes:block", "Yes");
+    if let Some(artwork) = &artwork {
+        let url = file_url(base_url, artwork);
+        rss.push_str(&format!("    <itunes:image href=\"{}\"/>\n", escape(&url)));
+        rss.push_str("    <image>\n");
+        push_element(&mut rss, 6, "url", &url);
+        push_element(&mut rss, 6, "title", &channel.full_title());
+        push_element(&mut rss, 6, "link", &format!("{}/", base_url));
+        rss.push_str("    </image>\n");
+    }
+
+    for (i, episode) in episodes.iter().enumerate() {
+        let url = file_url(base_url, &episode.relative);
+        rss.push_str("    <item>\n");
+        push_element(&mut rss, 6, "title", &episode.title);
+        push_element(&mut rss, 6, "itunes:title", &episode.title);
+        if let Some(description) = &episode.description {
+            push_element(&mut rss, 6, "description", description);
+        }
+        push_element(&mut rss, 6, "itunes:episode", &(i + 1).to_string());
+        push_element(&mut rss, 6, "itunes:episodeType", "full");
+        rss.push_str(&format!(
+            "      <enclosure url=\"{}\" length=\"{}\" type=\"{}\"/>\n",
+            escape(&url),
+            episode.size,
+            mime_type(&episode.path)
+        ));
+        rss.push_str(&format!(
+            "      <guid isPermaLink=\"false\">{}</guid>\n",
+            escape(&url)
+        ));
+        push_element(&mut rss, 6, "pubDate", &rfc2822(episode.published));
+        push_element(
+            &mut rss,
+            6,
+            "itunes:duration",
+            &((episode.duration / 1000.0).round() as u64).to_string(),
+        );
+
+        if !episode.chapters.is_empty() {
+            let chapters_file = format!("{}.chapters.json", episode.relative);
+            write_chapters_json(&dir.join(&chapters_file), &episode.chapters)?;
+            rss.push_str(&format!(
+                "      <podcast:chapters url=\"{}\" type=\"application/json+chapters\"/>\n",
+                escape(&file_url(base_url, &chapters_file))
+            ));
+        }
+        rss.push_str("    </item>\n");
+    }
+    rss.push_str("  </channel>\n</rss>\n");
+
+    fs::write(output, rss).map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+    Ok(episodes.len())
+}
+
+/// Audio files below `dir`, sorted by path
+pub fn audio_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    collect_audio_files(dir, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+fn collect_audio_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_audio_files(&path, files)?;
+        } else if is_audio(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Whether the file is an audiobook or chapter file by its extension
+pub fn is_audio(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| AUDIO_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+fn read_episode(dir: &Path, path: &Path) -> Result<Episode, String> {
+    let file = path.to_string_lossy().to_string();
+    let relative = path
+        .strip_prefix(dir)
+        .unwrap_or(path)
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<_>>()
+        .join("/");
+
+    let duration = ffmpeg::get_audio_length(&file)?;
+    let file_info = fs::metadata(path).map_err(|e| format!("Failed to read {}: {}", file, e))?;
+    let published = file_info
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).ok())
+        .map(|since| since.as_secs())
+        .unwrap_or(0);
+
+    // Not read_tags: that puts the album in the title, the episode wants the
+    // chapter or part title
+    let (title, description) = episode_tags(&file)?;
+    let title = title.unwrap_or_else(|| {
+        path.file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default()
+    });
+    // A single-chapter file doesn't need a chapter list
+    let chapters = ffmpeg::get_chapters(&file)?;
+    let chapters = if chapters.len() > 1 {
+        chapters
+    } else {
+        Vec::new()
+    };
+
+    Ok(Episode {
+        path: path.to_path_buf(),
+        relative,
+        title,
+        description,
+        duration,
+        size: file_info.len(),
+        published,
+        chapters,
+    })
+}
+
+// Title and description (or comment) tags of a file
+fn episode_tags(file: &str) -> Result<(Option<String>, Option<String>), String> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-show_entries", "format_tags", "-of", "json"])
+        .arg(file)
+        .output()
+        .map_err(|e| format!("Failed to execute ffprobe: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("ffprobe failed with status: {}", output.status));
+    }
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| format!("Failed to parse ffprobe output: {}", e))?;
+
+    let tag = |names: &[&str]| {
+        json["format"]["tags"].as_object().and_then(|tags| {
+            tags.iter()
+                .find(|(key, _)| names.contains(&key.to_lowercase().as_str()))
+                .and_then(|(_, value)| value.as_str())
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        })
+    };
+    Ok((
+        tag(&["title"]),
+        tag(&["description", "comment"]).map(|text| html_to_text(&text)),
+    ))
+}
+
+/// Podcasting 2.0 JSON chapters, start times in seconds
+fn write_chapters_json(path: &Path, chapters: &[(String, f64, f64)]) -> Result<(), String> {
+    let chapters: Vec<serde_json::Value> = chapters
+        .iter()
+        .map(|(title, start, _)| {
+            serde_json::json!({
+                "startTime": (start / 10.0).round() / 100.0,
+                "title": title,
+            })
+        })
+        .collect();
+    let json = serde_json::json!({ "version": "1.2.0", "chapters": chapters });
+    let text = serde_json::to_string_pretty(&json)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    fs::write(path, text).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+// The artwork must be served along with the audio, so it's copied into the feed folder
+fn copy_artwork(dir: &Path, cover: &Cover) -> Result<String, String> {
+    let name = match cover.mime_type() {
+        "image/png" => "cover.png",
+        _ => "cover.jpg",
+    };
+    fs::copy(&cover.path, dir.join(name))
+        .map_err(|e| format!("Failed to copy the cover to {}: {}", dir.display(), e))?;
+    Ok(name.to_string())
+}
+
+// A cover written by --sidecars
+fn find_artwork(dir: &Path) -> Option<String> {
+    ["cover.jpg", "cover.jpeg", "cover.png"]
+        .into_iter()
+        .find(|name| dir.join(name).is_file())
+        .map(|name| name.to_string())
+}
+
+fn mime_type(path: &Path) -> &'static str {
+    match path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase())
+        .as_deref()
+    {
+        Some("mp3") => "audio/mpeg",
+        Some("opus") | Some("ogg") => "audio/ogg",
+        Some("m4b") => "audio/x-m4b",
+        _ => "audio/x-m4a",
+    }
+}
+
+// base_url joined with the relative path, every segment percent-encoded
+fn file_url(base_url: &str, relative: &str) -> String {
+    let path = relative
+        .split('/')
+        .map(percent_encode)
+        .collect::<Vec<_>>()
+        .join("/");
+    format!("{}/{}", base_url, path)
+}
+
+fn percent_encode(segment: &str) -> String {
+    let mut encoded = String::new();
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn push_element(rss: &mut String, indent: usize, name: &str, text: &str) {
+    rss.push_str(&format!(
+        "{}<{}>{}</{}>\n",
+        " ".repeat(indent),
+        name,
+        escape(text),
+        name
+    ));
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Seconds since the epoch as an RFC 2822 date, e.g. "Tue, 03 Jun 2025 14:05:09 +0000"
+pub fn rfc2822(seconds: u64) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let days = seconds / 86_400;
+    let time = seconds % 86_400;
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} +0000",
+        DAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rfc2822_dates_cross_leap_days_and_centuries() {
+        assert_eq!(rfc2822(0), "Thu, 01 Jan 1970 00:00:00 +0000");
+        assert_eq!(rfc2822(951_782_400), "Tue, 29 Feb 2000 00:00:00 +0000");
+        assert_eq!(rfc2822(951_868_799), "Tue, 29 Feb 2000 23:59:59 +0000");
+        assert_eq!(rfc2822(1_735_689_599), "Tue, 31 Dec 2024 23:59:59 +0000");
+        assert_eq!(rfc2822(1_760_792_523), "Sat, 18 Oct 2025 13:02:03 +0000");
+        // 2100 is not a leap year
+        assert_eq!(rfc2822(4_107_542_400), "Mon, 01 Mar 2100 00:00:00 +0000");
+    }
+
+    #[test]
+    fn file_urls_encode_every_segment() {
+        assert_eq!(
+            file_url(
+                "https://example.com/books",
+                "Frank Herbert/Dune #1 & more.m4b"
+            ),
+            "https://example.com/books/Frank%20Herbert/Dune%20%231%20%26%20more.m4b"
+        );
+        assert_eq!(
+            file_url("https://example.com", "Émile.mp3"),
+            "https://example.com/%C3%89mile.mp3"
+        );
+    }
+}
@@ -1,3 +1,6 @@
+use crate::postprocess::{self, Loudness, PostProcess};
+use crate::progress::say;
+use crate::tts::{TtsFormat, SAMPLE_RATE};
 use core::str;
 use std::fs::{self, File};
 use std::io;
@@ -5,18 +8,23 @@ use std::io::Write;
 use std::path::Path;
 use std::process::{Command, Stdio};
 
-pub fn create_silence_if_not_exists(duration: f64, output_path: &str) {
+/// Seconds of silence between two paragraphs, unless --pause says otherwise
+pub const PARAGRAPH_SILENCE: f64 = 1.0;
+
+pub fn create_silence_if_not_exists(duration: f64, output_path: &str, encoder_args: &[&str]) {
     if !Path::new(output_path).exists() {
+        // Same rate and layout as the 24 kHz mono paragraphs from edge-tts
         Command::new("ffmpeg")
             .args(&[
                 "-f",
                 "lavfi",
                 "-i",
-                "anullsrc=r=44100:cl=mono",
+                &format!("anullsrc=r={}:cl=mono", SAMPLE_RATE),
                 "-t",
                 &duration.to_string(),
-                output_path,
             ])
+            .args(encoder_args)
+            .arg(output_path)
             .stdout(Stdio::null()) // Hide standard output
             .stderr(Stdio::null())
             .status()
@@ -35,27 +43,33 @@ pub fn create_chapter_file<P: AsRef<Path>>(
     writeln!(file, ";FFMETADATA1")?;
 
     // Calculate chapter times and write to the file
-    let mut start_time: f64 = 0.0;
-
-    for (i, name) in chapter_names.iter().enumerate() {
-        let end_time;
-        if i < chapter_lengths.len() {
-            end_time = start_time + chapter_lengths[i];
-        } else {
-            end_time = chapter_lengths.iter().sum();
-        }
-
+    for (name, start_time, end_time) in chapter_marks(&chapter_lengths, &chapter_names) {
         writeln!(file, "[CHAPTER]")?;
         writeln!(file, "TIMEBASE=1/1000")?;
         writeln!(file, "START={}", start_time)?;
         writeln!(file, "END={}", end_time)?;
         writeln!(file, "title={}", name)?;
+    }
+
+    Ok(())
+}
+
+/// Start and end (in ms) of every chapter when the chapters are played back to back
+pub fn chapter_marks(chapter_lengths: &[f64], chapter_names: &[&str]) -> Vec<(String, f64, f64)> {
+    let mut marks = Vec::new();
+    let mut start_time: f64 = 0.0;
+
+    for (i, name) in chapter_names.iter().enumerate() {
+        let end_time = match chapter_lengths.get(i) {
+            Some(length) => start_time + length,
+            None => chapter_lengths.iter().sum(),
+        };
+        marks.push((name.to_string(), start_time, end_time));
 
         // Update start_time for the next chapter
         start_time = end_time; // Adding 1 ms to avoid overlap <- That was my downfall aka don't add that 1 ms
     }
-
-    Ok(())
+    marks
 }
 pub fn get_audio_length(file_path: &str) -> Result<f64, String> {
     // Prepare the command to call ffprobe
@@ -89,6 +103,49 @@ pub fn get_audio_length(file_path: &str) -> Result<f64, String> {
     Ok(duration_ms) // Return the duration in milliseconds
 }
 
+/// Title, start and end (in ms) of the chapters embedded in an audio file
+pub fn get_chapters(file_path: &str) -> Result<Vec<(String, f64, f64)>, String> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-show_chapters", "-of", "json"])
+        .arg(file_path)
+        .output()
+        .map_err(|e| format!("Failed to execute ffprobe: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("ffprobe failed with status: {}", output.status));
+    }
+
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| format!("Failed to parse ffprobe output: {}", e))?;
+    let seconds = |value: &serde_json::Value| {
+        value
+            .as_str()
+            .and_then(|s| s.parse::<f64>().ok())
+            .unwrap_or(0.0)
+            * 1000.0
+    };
+    let chapters = json["chapters"]
+        .as_array()
+        .map(|chapters| {
+            chapters
+                .iter()
+                .enumerate()
+                .map(|(i, chapter)| {
+                    let title = chapter["tags"]["title"]
+                        .as_str()
+                        .map(|title| title.to_string())
+                        .unwrap_or_else(|| format!("Chapter {}", i + 1));
+                    (
+                        title,
+                        seconds(&chapter["start_time"]),
+                        seconds(&chapter["end_time"]),
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok(chapters)
+}
+
 pub fn add_chapter_data(
     chapter_file: &str,
     chapter_files: Vec<String>,
@@ -102,10 +159,15 @@ pub fn add_chapter_data(
         let mut file_list = File::create(file_list_path)?;
         for chapter in chapter_files {
             writeln!(file_list, "file '{}'", chapter)?;
-            println!("{chapter}");
+            say!("{chapter}");
         }
     }
-    let tmp_file = "temp_output_file.m4a";
+    // Keep the container of the chapter files for the joined file
+    let extension = Path::new(output_file)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_string())
+        .unwrap_or_else(|| "m4a".to_string());
+    let tmp_file = format!("temp_output_file.{}", extension);
     // Execute the ffmpeg command
     let _concat_status = Command::new("ffmpeg")
         .arg("-f")
@@ -116,12 +178,12 @@ pub fn add_chapter_data(
         .arg(file_list_path) // Input file list
         .arg("-c")
         .arg("copy") // Copy the streams
-        .arg(tmp_file) // Temporary output file
+        .arg(&tmp_file) // Temporary output file
         .status()?;
 
     let _chapter_status = Command::new("ffmpeg")
         .arg("-i")
-        .arg(tmp_file) // Input file from the first command
+        .arg(&tmp_file) // Input file from the first command
         .arg("-i")
         .arg(chapter_file) // Chapter file
         .arg("-map_metadata")
@@ -130,7 +192,7 @@ pub fn add_chapter_data(
         .arg("copy") // Copy the streams
         .arg(output_file) // Final output file
         .status()?;
-    fs::remove_file(tmp_file).ok();
+    fs::remove_file(&tmp_file).ok();
 
     // Optionally, you might want to remove the temporary file list after execution
     fs::remove_file(file_list_path).ok(); // Ignore any error in removing the file
@@ -139,12 +201,23 @@ pub fn add_chapter_data(
     Ok(())
 }
 
-pub fn concatenate_audio_files(input_files: Vec<String>, output_file: &str) {
-    let temp_silence = "silence.wav";
-    let silence_duration = 1.0; // Duration of silence in seconds
+/// Joins the paragraphs of a chapter with `pause` seconds of silence between them, running
+/// the post-processing filters on the way. Returns the loudness after normalization, if requested.
+pub fn concatenate_audio_files(
+    input_files: Vec<String>,
+    output_file: &str,
+    encoder_args: &[String],
+    source: TtsFormat,
+    post: &PostProcess,
+    pause: f64,
+) -> Option<Loudness> {
+    // Silence is encoded like the paragraphs so the streams can also be copied as is
+    let temp_silence = format!("silence.{}", source.extension());
 
     // Create silence if it doesn't exist
-    create_silence_if_not_exists(silence_duration, temp_silence);
+    if pause > 0.0 {
+        create_silence_if_not_exists(pause, &temp_silence, &source.encoder_args());
+    }
 
     // Create a temporary file for the concat
     let input_list_file = "inputs.txt";
@@ -153,43 +226,61 @@ pub fn concatenate_audio_files(input_files: Vec<String>, output_file: &str) {
     // Write audio files and silence into the input list
     for i in 0..input_files.len() {
         writeln!(file, "file '{}'", input_files[i]).expect("Failed to write to input list file");
-        if i < input_files.len() - 1 {
+        if i < input_files.len() - 1 && pause > 0.0 {
             writeln!(file, "file '{}'", temp_silence)
                 .expect("Failed to write silence to input list file");
         }
     }
-    println!("Combining Files With FFmpeg ");
+    drop(file);
+
+    // First loudnorm pass, the second one happens while encoding
+    let measured = if post.loudness.is_some() {
+        match postprocess::measure_loudness(input_list_file, post) {
+            Ok(loudness) => Some(loudness),
+            Err(e) => {
+                eprintln!("{}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let filters = post.filters(measured.as_ref(), SAMPLE_RATE);
+
+    say!("Combining Files With FFmpeg ");
     // Re-encode and concatenate audio files
-    let status = Command::new("ffmpeg")
-        .args(&[
-            "-f",
-            "concat",
-            "-safe",
-            "0",
-            "-i",
-            input_list_file,
-            "-c",
-            "aac",
-            "-b:a",
-            "69k",
-            output_file,
-        ])
+    let mut command = Command::new("ffmpeg");
+    command.args(["-f", "concat", "-safe", "0", "-i", input_list_file]);
+    if !filters.is_empty() {
+        command.arg("-af").arg(filters.join(","));
+    }
+    let output = command
+        .args(encoder_args)
+        .arg(output_file)
         .stdout(Stdio::null()) // Hide standard output
-        .stderr(Stdio::null()) // Hide standard error
-        .status()
+        .output()
         .expect("Failed to concatenate audio files");
 
-    if status.success() {
-        println!("Audio files concatenated successfully.");
+    let mut loudness = None;
+    if output.status.success() {
+        say!("Audio files concatenated successfully.");
+        if post.loudness.is_some() {
+            loudness =
+                postprocess::parse_loudnorm(&String::from_utf8_lossy(&output.stderr), "output");
+        }
     } else {
-        println!("ffmpeg failed with status: {}", status);
+        say!("ffmpeg failed with status: {}", output.status);
     }
 
     // Cleanup: Remove all input files and temporary files
     fs::remove_file(input_list_file).expect("Failed to remove input list file");
-    fs::remove_file(temp_silence).expect("Failed to remove silence file");
+    if pause > 0.0 {
+        fs::remove_file(&temp_silence).expect("Failed to remove silence file");
+    }
 
     for input_file in input_files {
         fs::remove_file(input_file).expect("Failed to remove input audio file");
     }
+
+    loudness
 }
@@ -0,0 +1,119 @@
+// src/input.rs
+mod docx;
+mod fb2;
+mod gutenberg;
+mod html;
+mod txt;
+
+use crate::book::Book;
+use std::io;
+use std::path::Path;
+
+/// A source document format that can be turned into a `Book`.
+pub trait InputFormat {
+    /// Name used to select the format with `--input-format`
+    fn name(&self) -> &'static str;
+
+    /// Lowercase file extensions (without the dot) handled by this format
+    fn extensions(&self) -> &'static [&'static str];
+
+    /// Whether this format should handle the given file when no format was requested.
+    /// Defaults to matching the file extension.
+    fn matches(&self, path: &Path) -> bool {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) => self.extensions().contains(&ext.to_lowercase().as_str()),
+            None => false,
+        }
+    }
+
+    /// Parse the file into chapters, each starting with its title followed by paragraphs
+    fn read_book(&self, path: &Path) -> io::Result<Book>;
+}
+
+// Order matters: Gutenberg texts are also `.txt`, so they have to be sniffed first
+fn formats() -> Vec<Box<dyn InputFormat>> {
+    vec![
+        Box::new(gutenberg::Gutenberg),
+        Box::new(txt::Txt),
+        Box::new(html::Html),
+        Box::new(fb2::Fb2),
+        Box::new(docx::Docx),
+    ]
+}
+
+/// Names of all supported input formats, for help and error messages
+pub fn format_names() -> Vec<&'static str> {
+    formats().iter().map(|format| format.name()).collect()
+}
+
+/// Pick the input format by name if one was requested, otherwise by looking at the file.
+pub fn find_format(path: &str, requested: Option<&str>) -> Option<Box<dyn InputFormat>> {
+    let path = Path::new(path);
+    match requested {
+        Some(name) => formats()
+            .into_iter()
+            .find(|format| format.name().eq_ignore_ascii_case(name)),
+        None => formats().into_iter().find(|format| format.matches(path)),
+    }
+}
+
+// Collapse runs of whitespace (including newlines) into single spaces
+fn normalize_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// Helper shared by the formats: adds a chapter with its title as the first paragraph,
+// skipping chapters that have no text besides the title
+fn push_chapter(book: &mut Book, title: &str, paragraphs: &mut Vec<String>) {
+    if !paragraphs.is_empty() {
+        let mut content = vec![title.to_string()];
+        content.append(paragraphs);
+        book.add_chapter(title, content);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    // Writes a file for a reader to read, in a folder of its own in the system temp folder
+    pub(super) fn fixture(name: &str, contents: &[u8]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("edgeab-input-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    // The chapters as (title, paragraphs) for comparing
+    pub(super) fn chapters(book: &Book) -> Vec<(String, Vec<String>)> {
+        book.get_all_chapters()
+            .into_iter()
+            .map(|(title, content)| (title.clone(), content.clone()))
+            .collect()
+    }
+
+    pub(super) fn chapter(title: &str, content: &[&str]) -> (String, Vec<String>) {
+        let content = content.iter().map(|text| text.to_string()).collect();
+        (title.to_string(), content)
+    }
+
+    #[test]
+    fn gutenberg_text_is_sniffed_before_plain_text() {
+        let gutenberg = fixture(
+            "sniffed-gutenberg.txt",
+            b"The Project Gutenberg eBook\n\n*** START OF THE PROJECT GUTENBERG EBOOK ***\n",
+        );
+        let plain = fixture("sniffed-plain.txt", b"# One\nText\n");
+        let name = |path: &Path, requested| {
+            find_format(&path.to_string_lossy(), requested).map(|format| format.name())
+        };
+        assert_eq!(name(&gutenberg, None), Some("gutenberg"));
+        assert_eq!(name(&plain, None), Some("txt"));
+        assert_eq!(name(&gutenberg, Some("TXT")), Some("txt"));
+        assert_eq!(name(Path::new("book.FB2"), None), Some("fb2"));
+        assert_eq!(name(Path::new("book.pdf"), None), None);
+    }
+}
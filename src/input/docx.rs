@@ -0,0 +1,157 @@
+// src/input/docx.rs
+use super::{normalize_whitespace, push_chapter, InputFormat};
+use crate::book::Book;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use xmltree::{Element, XMLNode};
+
+/// Word documents; paragraphs styled `Title`, `Heading1` or `Heading2` start chapters
+pub struct Docx;
+
+impl InputFormat for Docx {
+    fn name(&self) -> &'static str {
+        "docx"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["docx"]
+    }
+
+    fn read_book(&self, path: &Path) -> io::Result<Book> {
+        let mut archive = zip::ZipArchive::new(BufReader::new(File::open(path)?))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+        let mut document_xml = String::new();
+        archive
+            .by_name("word/document.xml")
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?
+            .read_to_string(&mut document_xml)?;
+
+        let root = Element::parse(document_xml.as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        let body = root.get_child("body").ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "DOCX has no document body")
+        })?;
+
+        let file_title = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut current_title = file_title;
+        let mut paragraphs = Vec::new();
+        let mut book = Book::new();
+
+        for paragraph in body
+            .children
+            .iter()
+            .filter_map(|node| node.as_element())
+            .filter(|elem| elem.name == "p")
+        {
+            let text = normalize_whitespace(&paragraph_text(paragraph));
+            if text.is_empty() {
+                continue;
+            }
+
+            if is_heading(paragraph) {
+                push_chapter(&mut book, &current_title, &mut paragraphs);
+                current_title = text;
+            } else {
+                paragraphs.push(text);
+            }
+        }
+        push_chapter(&mut book, &current_title, &mut paragraphs);
+
+        Ok(book)
+    }
+}
+
+// Reads <w:pPr><w:pStyle w:val="Heading1"/></w:pPr>
+fn is_heading(paragraph: &Element) -> bool {
+    let style = paragraph
+        .get_child("pPr")
+        .and_then(|properties| properties.get_child("pStyle"))
+        .and_then(|style| style.attributes.get("val"))
+        .map(|style| style.to_lowercase());
+
+    matches!(
+        style.as_deref(),
+        Some("title") | Some("heading1") | Some("heading2")
+    )
+}
+
+// Concatenates the <w:t> runs of a paragraph, turning tabs and breaks into spaces
+fn paragraph_text(element: &Element) -> String {
+    let mut text = String::new();
+    for node in &element.children {
+        if let XMLNode::Element(child) = node {
+            match child.name.as_str() {
+                "t" => {
+                    for inner in &child.children {
+                        if let XMLNode::Text(t) = inner {
+                            text.push_str(t);
+                        }
+                    }
+                }
+                "tab" | "br" | "cr" => text.push(' '),
+                // Deleted text in tracked changes shouldn't be read aloud
+                "del" => {}
+                _ => text.push_str(&paragraph_text(child)),
+            }
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::tests::{chapter, chapters, fixture};
+    use std::io::Write;
+
+    // A .docx with only the document part, which is all the reader looks at
+    fn docx(name: &str, body: &str) -> std::path::PathBuf {
+        let mut archive = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+        archive
+            .start_file(
+                "word/document.xml",
+                zip::write::SimpleFileOptions::default(),
+            )
+            .unwrap();
+        write!(
+            archive,
+            r#"<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:body>{}</w:body></w:document>"#,
+            body
+        )
+        .unwrap();
+        fixture(name, &archive.finish().unwrap().into_inner())
+    }
+
+    #[test]
+    fn heading_styles_start_chapters() {
+        let path = docx(
+            "story.docx",
+            r#"<w:p><w:r><w:t>Before</w:t></w:r></w:p>
+               <w:p><w:pPr><w:pStyle w:val="Heading1"/></w:pPr><w:r><w:t>One</w:t></w:r></w:p>
+               <w:p><w:r><w:t>Hello</w:t></w:r><w:r><w:tab/><w:t>world</w:t></w:r>
+                 <w:del><w:r><w:delText>deleted</w:delText></w:r></w:del></w:p>
+               <w:p><w:pPr><w:pStyle w:val="Heading2"/></w:pPr><w:r><w:t>Two</w:t></w:r></w:p>
+               <w:p><w:r><w:t>Last</w:t></w:r></w:p>"#,
+        );
+        let book = Docx.read_book(&path).unwrap();
+        assert_eq!(
+            chapters(&book),
+            vec![
+                chapter("story", &["story", "Before"]),
+                chapter("One", &["One", "Hello world"]),
+                chapter("Two", &["Two", "Last"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn file_that_is_not_a_zip_is_an_error() {
+        let path = fixture("broken.docx", b"not a zip");
+        assert!(Docx.read_book(&path).is_err());
+    }
+}
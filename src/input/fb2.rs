@@ -0,0 +1,153 @@
+// src/input/fb2.rs
+use super::{normalize_whitespace, push_chapter, InputFormat};
+use crate::book::Book;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use xmltree::{Element, XMLNode};
+
+/// FictionBook 2 documents: every titled `<section>` of the main `<body>` becomes a chapter
+pub struct Fb2;
+
+impl InputFormat for Fb2 {
+    fn name(&self) -> &'static str {
+        "fb2"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["fb2"]
+    }
+
+    fn read_book(&self, path: &Path) -> io::Result<Book> {
+        let reader = BufReader::new(File::open(path)?);
+        let root = Element::parse(reader)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+        // Footnotes and comments live in extra bodies with a name attribute
+        let body = root
+            .children
+            .iter()
+            .filter_map(|node| node.as_element())
+            .find(|elem| elem.name == "body" && !elem.attributes.contains_key("name"))
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "FB2 file has no <body>"))?;
+
+        let book_title = root
+            .get_child("description")
+            .and_then(|description| description.get_child("title-info"))
+            .and_then(|info| info.get_child("book-title"))
+            .map(element_text)
+            .filter(|title| !title.is_empty());
+
+        let mut book = Book::new();
+        if let Some(title) = &book_title {
+            book.set_title(title);
+        }
+        read_section(body, book_title.as_deref().unwrap_or("Untitled"), &mut book);
+        Ok(book)
+    }
+}
+
+// Walks a section (or the body) and adds its text as a chapter; nested sections become
+// their own chapters so a part/chapter hierarchy is flattened in reading order
+fn read_section(section: &Element, fallback_title: &str, book: &mut Book) {
+    let title = section
+        .get_child("title")
+        .map(element_text)
+        .filter(|title| !title.is_empty())
+        .unwrap_or_else(|| fallback_title.to_string());
+    let mut paragraphs = Vec::new();
+
+    for child in section.children.iter().filter_map(|node| node.as_element()) {
+        match child.name.as_str() {
+            "section" => {
+                push_chapter(book, &title, &mut paragraphs);
+                read_section(child, &title, book);
+            }
+            "p" | "subtitle" | "text-author" => push_text(child, &mut paragraphs),
+            "poem" | "cite" | "epigraph" | "stanza" => collect_blocks(child, &mut paragraphs),
+            _ => {}
+        }
+    }
+    push_chapter(book, &title, &mut paragraphs);
+}
+
+fn collect_blocks(element: &Element, paragraphs: &mut Vec<String>) {
+    for child in element.children.iter().filter_map(|node| node.as_element()) {
+        match child.name.as_str() {
+            "p" | "v" | "subtitle" | "text-author" => push_text(child, paragraphs),
+            "title" | "stanza" => collect_blocks(child, paragraphs),
+            _ => {}
+        }
+    }
+}
+
+fn push_text(element: &Element, paragraphs: &mut Vec<String>) {
+    let text = element_text(element);
+    if !text.is_empty() {
+        paragraphs.push(text);
+    }
+}
+
+// Text of an element including inline markup such as <emphasis> and <strong>
+fn element_text(element: &Element) -> String {
+    fn collect(element: &Element, out: &mut String) {
+        for node in &element.children {
+            match node {
+                XMLNode::Text(text) | XMLNode::CData(text) => out.push_str(text),
+                XMLNode::Element(child) => {
+                    // Titles are made of several <p>s, keep them apart
+                    if child.name == "p" && !out.is_empty() {
+                        out.push(' ');
+                    }
+                    collect(child, out);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut text = String::new();
+    collect(element, &mut text);
+    normalize_whitespace(&text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::tests::{chapter, chapters, fixture};
+
+    #[test]
+    fn sections_become_chapters_and_notes_are_left_out() {
+        let path = fixture(
+            "book.fb2",
+            br#"<?xml version="1.0" encoding="utf-8"?>
+<FictionBook xmlns="http://www.gribuser.ru/xml/fictionbook/2.0">
+  <description><title-info><book-title>Tale</book-title></title-info></description>
+  <body>
+    <section>
+      <title><p>Part</p><p>One</p></title>
+      <p>Hello <emphasis>there</emphasis>.</p>
+      <poem><stanza><v>A line</v></stanza></poem>
+      <section><title><p>Inner</p></title><p>Deep</p></section>
+    </section>
+  </body>
+  <body name="notes"><section><p>A footnote</p></section></body>
+</FictionBook>"#,
+        );
+        let book = Fb2.read_book(&path).unwrap();
+        assert_eq!(book.get_title(), Some("Tale"));
+        assert_eq!(
+            chapters(&book),
+            vec![
+                chapter("Part One", &["Part One", "Hello there.", "A line"]),
+                chapter("Inner", &["Inner", "Deep"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn file_without_body_is_an_error() {
+        let path = fixture("empty.fb2", b"<FictionBook><description/></FictionBook>");
+        assert!(Fb2.read_book(&path).is_err());
+    }
+}
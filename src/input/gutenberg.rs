@@ -0,0 +1,155 @@
+// src/input/gutenberg.rs
+use super::{normalize_whitespace, push_chapter, InputFormat};
+use crate::book::Book;
+use regex::Regex;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Project Gutenberg plain text: the license header and footer are stripped and
+/// headings such as `CHAPTER I.` start chapters
+pub struct Gutenberg;
+
+const START_MARKER: &str = "*** START OF";
+const END_MARKER: &str = "*** END OF";
+
+impl InputFormat for Gutenberg {
+    fn name(&self) -> &'static str {
+        "gutenberg"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["txt"]
+    }
+
+    // Only claim .txt files that carry the Gutenberg start marker
+    fn matches(&self, path: &Path) -> bool {
+        let is_txt = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("txt"));
+        is_txt
+            && fs::read_to_string(path)
+                .map(|text| text.contains(START_MARKER) && text.contains("PROJECT GUTENBERG"))
+                .unwrap_or(false)
+    }
+
+    fn read_book(&self, path: &Path) -> io::Result<Book> {
+        let source = fs::read_to_string(path)?;
+        let source = source.replace("\r\n", "\n");
+        let text = strip_license(&source);
+
+        let heading = Regex::new(
+            r"^(CHAPTER|Chapter|BOOK|Book|PART|Part|STAVE|Stave)\s+([IVXLCDM]+|\d+|[A-Z][A-Za-z-]+)\b",
+        )
+        .unwrap();
+
+        // Anything before the first heading (title page, contents) is front matter
+        let mut current_title: Option<String> = None;
+        let mut paragraphs = Vec::new();
+        let mut book = Book::new();
+        if let Some(title) = header_title(&source) {
+            book.set_title(&title);
+        }
+
+        for block in text.split("\n\n") {
+            let lines: Vec<&str> = block
+                .lines()
+                .map(|line| line.trim())
+                .filter(|line| !line.is_empty())
+                .collect();
+            if lines.is_empty() {
+                continue;
+            }
+
+            // A heading is a short block: "CHAPTER I." optionally followed by its name.
+            // Contents pages list many headings in one block, so they don't match.
+            if lines.len() <= 2 && heading.is_match(lines[0]) {
+                if let Some(title) = &current_title {
+                    push_chapter(&mut book, title, &mut paragraphs);
+                }
+                paragraphs.clear();
+                current_title = Some(normalize_whitespace(&lines.join(" ")));
+            } else {
+                paragraphs.push(normalize_whitespace(&lines.join(" ")));
+            }
+        }
+
+        match current_title {
+            Some(title) => push_chapter(&mut book, &title, &mut paragraphs),
+            // No headings at all: read the whole text as one chapter
+            None => {
+                let title = header_title(&source).unwrap_or_else(|| "Chapter 1".to_string());
+                push_chapter(&mut book, &title, &mut paragraphs);
+            }
+        }
+
+        Ok(book)
+    }
+}
+
+// Keeps only the text between the "*** START OF ..." and "*** END OF ..." lines
+fn strip_license(source: &str) -> &str {
+    let start = source
+        .find(START_MARKER)
+        .and_then(|pos| source[pos..].find('\n').map(|end| pos + end + 1))
+        .unwrap_or(0);
+    let end = source[start..]
+        .find(END_MARKER)
+        .map(|pos| start + pos)
+        .unwrap_or(source.len());
+    &source[start..end]
+}
+
+// The "Title: ..." line of the Gutenberg header
+fn header_title(source: &str) -> Option<String> {
+    source
+        .lines()
+        .find_map(|line| line.strip_prefix("Title:"))
+        .map(|title| title.trim().to_string())
+        .filter(|title| !title.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::tests::{chapter, chapters, fixture};
+
+    #[test]
+    fn license_is_stripped_and_headings_start_chapters() {
+        let path = fixture(
+            "pg-book.txt",
+            b"The Project Gutenberg eBook of Tale\r\n\r\nTitle: Tale\r\n\r\n\
+              *** START OF THE PROJECT GUTENBERG EBOOK TALE ***\r\n\r\n\
+              CONTENTS\r\n\r\nCHAPTER I.\r\nCHAPTER II.\r\nCHAPTER III.\r\n\r\n\
+              CHAPTER I.\r\nThe Start\r\n\r\nFirst paragraph\r\nwrapped.\r\n\r\n\
+              CHAPTER II.\r\n\r\nSecond.\r\n\r\n\
+              *** END OF THE PROJECT GUTENBERG EBOOK TALE ***\r\n\r\nLicense text\r\n",
+        );
+        assert!(Gutenberg.matches(&path));
+        let book = Gutenberg.read_book(&path).unwrap();
+        assert_eq!(book.get_title(), Some("Tale"));
+        assert_eq!(
+            chapters(&book),
+            vec![
+                chapter(
+                    "CHAPTER I. The Start",
+                    &["CHAPTER I. The Start", "First paragraph wrapped."]
+                ),
+                chapter("CHAPTER II.", &["CHAPTER II.", "Second."]),
+            ]
+        );
+    }
+
+    #[test]
+    fn text_without_headings_is_one_chapter() {
+        let path = fixture(
+            "pg-short.txt",
+            b"Title: Short\n\n*** START OF THE PROJECT GUTENBERG EBOOK ***\n\nJust text.\n",
+        );
+        let book = Gutenberg.read_book(&path).unwrap();
+        assert_eq!(
+            chapters(&book),
+            vec![chapter("Short", &["Short", "Just text."])]
+        );
+    }
+}
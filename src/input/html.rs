@@ -0,0 +1,101 @@
+// src/input/html.rs
+use super::{normalize_whitespace, push_chapter, InputFormat};
+use crate::book::Book;
+use scraper::{Html as Document, Selector};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// A single HTML file; `h1`/`h2` headings start chapters and block elements become paragraphs
+pub struct Html;
+
+impl InputFormat for Html {
+    fn name(&self) -> &'static str {
+        "html"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["html", "htm", "xhtml"]
+    }
+
+    fn read_book(&self, path: &Path) -> io::Result<Book> {
+        let source = fs::read_to_string(path)?;
+        let document = Document::parse_document(&source);
+
+        let block_selector =
+            Selector::parse("h1, h2, h3, h4, h5, h6, p, li, blockquote, pre").unwrap();
+        let title_selector = Selector::parse("title").unwrap();
+
+        let document_title = document
+            .select(&title_selector)
+            .next()
+            .map(|title| normalize_whitespace(&title.text().collect::<String>()))
+            .filter(|title| !title.is_empty());
+        let mut paragraphs = Vec::new();
+        let mut book = Book::new();
+        if let Some(title) = &document_title {
+            book.set_title(title);
+        }
+
+        // Text before the first heading goes into a chapter named after the document
+        let mut current_title = document_title.unwrap_or_else(|| "Introduction".to_string());
+
+        for element in document.select(&block_selector) {
+            // Nested blocks (a <p> inside a <blockquote> or <li>) are read through their parent
+            let nested = element.ancestors().any(|ancestor| {
+                ancestor
+                    .value()
+                    .as_element()
+                    .is_some_and(|e| matches!(e.name(), "p" | "li" | "blockquote" | "pre"))
+            });
+            if nested {
+                continue;
+            }
+
+            let text = normalize_whitespace(&element.text().collect::<Vec<_>>().join(" "));
+            if text.is_empty() {
+                continue;
+            }
+
+            match element.value().name() {
+                "h1" | "h2" => {
+                    push_chapter(&mut book, &current_title, &mut paragraphs);
+                    current_title = text;
+                }
+                _ => paragraphs.push(text),
+            }
+        }
+        push_chapter(&mut book, &current_title, &mut paragraphs);
+
+        Ok(book)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::tests::{chapter, chapters, fixture};
+
+    #[test]
+    fn headings_start_chapters_and_blocks_are_paragraphs() {
+        let path = fixture(
+            "book.html",
+            b"<html><head><title>The  Book</title></head><body>\
+              <p>Before any heading</p>\
+              <h1>First</h1><p>One <em>two</em></p>\
+              <blockquote><p>Quoted</p></blockquote>\
+              <h2>Second</h2><ul><li>Item</li></ul><h3>Minor</h3>\
+              </body></html>",
+        );
+        let book = Html.read_book(&path).unwrap();
+        assert_eq!(book.get_title(), Some("The Book"));
+        assert_eq!(
+            chapters(&book),
+            vec![
+                chapter("The Book", &["The Book", "Before any heading"]),
+                chapter("First", &["First", "One two", "Quoted"]),
+                chapter("Second", &["Second", "Item", "Minor"]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,59 @@
+// src/input/txt.rs
+use super::InputFormat;
+use crate::book::{get_titles, read_sections, Book};
+use std::io;
+use std::path::Path;
+
+/// The intermediate text format written by `epub::make_file`: `# Title` lines start chapters
+pub struct Txt;
+
+impl InputFormat for Txt {
+    fn name(&self) -> &'static str {
+        "txt"
+    }
+
+    fn extensions(&self) -> &'static [&'static str] {
+        &["txt", "md"]
+    }
+
+    fn read_book(&self, path: &Path) -> io::Result<Book> {
+        let path = path.to_string_lossy();
+        let chapters = read_sections(&path)?;
+        let titles = get_titles(&path)?;
+        let min_length = chapters.len().min(titles.len());
+
+        let mut book = Book::new();
+        for i in 0..min_length {
+            book.add_chapter(&titles[i], chapters[i].clone());
+        }
+        Ok(book)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::tests::{chapter, chapters, fixture};
+
+    #[test]
+    fn headings_start_chapters() {
+        let path = fixture(
+            "book.txt",
+            b"# One\nFirst\n\nSecond\n## Not a chapter\n# Two\nThird\n",
+        );
+        let book = Txt.read_book(&path).unwrap();
+        assert_eq!(
+            chapters(&book),
+            vec![
+                chapter("One", &["One", "First", "Second", "## Not a chapter"]),
+                chapter("Two", &["Two", "Third"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_file_is_an_error() {
+        let path = std::env::temp_dir().join("edgeab-input-missing.txt");
+        assert!(Txt.read_book(&path).is_err());
+    }
+}
@@ -1,40 +1,118 @@
 // src/main.rs
+mod batch;
 mod book;
+mod config;
+mod cover;
 mod epub;
+mod estimate;
+mod feed;
 mod ffmpeg;
+mod input;
 mod metdata;
-use book::{get_titles, read_sections, Book}; // Import book module functions
-use clap::Parser;
+mod naming;
+mod output;
+mod overlay;
+mod postprocess;
+mod progress;
+mod sample;
+mod selection;
+mod server;
+mod split;
+mod transcript;
+mod tts;
+mod watch;
+use batch::{BatchItem, Outcome};
+use book::Book;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
 use colored::*;
-use edge_tts::{build_ssml, request_audio};
+use cover::{Cover, CoverSettings, CoverTemplate};
 use ffmpeg::concatenate_audio_files;
 use indicatif::{ProgressBar, ProgressStyle};
+use metdata::BookMetadata;
+use naming::OutputNaming;
+use output::{EncoderSettings, OutputFormat};
+use overlay::SpokenParagraph;
+use postprocess::{Loudness, PostProcess};
+use progress::{say, Progress, ProgressMode};
+use split::SplitLimits;
+use std::collections::BTreeMap;
 use std::fs::{self, OpenOptions};
 use std::io;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::task;
+use transcript::{Cue, TranscriptFormat};
+use tts::{EngineKind, TtsEngine, TtsFormat, Voice};
 
-const AUDIO_OUTPUT_DIR: &str = "./tmp"; // Set the output / temp directory
+const AUDIO_OUTPUT_DIR: &str = "./tmp"; // The temp folder unless another is given
 
-async fn read_chapter(chapter_number: usize, texts: Vec<String>) {
+/// Settings for turning a `Book` into an audiobook
+#[derive(Clone)]
+struct BuildOptions {
+    output_format: OutputFormat,
+    encoder: EncoderSettings,
+    tts_format: TtsFormat,
+    post: PostProcess,
+    split: SplitLimits,
+    /// Output directory and file name template
+    naming: OutputNaming,
+    /// Write Audiobookshelf/Plex sidecar files next to the audio
+    sidecars: bool,
+    /// Transcript formats written next to the audio
+    transcripts: Vec<TranscriptFormat>,
+    /// Source EPUB to turn into a read-along EPUB with media overlays
+    read_along: Option<String>,
+    cover: CoverSettings,
+    /// Layout of the cover generated when neither --cover nor the OPF has one
+    cover_template: CoverTemplate,
+    /// `--meta-file` and `--meta` values layered over the OPF metadata
+    metadata_overrides: Vec<(String, String)>,
+    engine: Arc<dyn TtsEngine>,
+    voice: Voice,
+    /// Counters of the conversion, for reporting it elsewhere than the terminal
+    progress: Arc<Progress>,
+    dry_run: bool,
+    /// Seconds of silence between paragraphs
+    pause: f64,
+    /// EPUB chapters whose title contains one of these are left out
+    skip_chapters: Vec<String>,
+    /// Only these chapters (from 1) are synthesized
+    chapters: Option<selection::Selection>,
+    /// Chapter set the selected chapters replace chapters in, instead of making a sample
+    splice_into: Option<PathBuf>,
+    /// Replace a book made before at the same path instead of writing "Title (2)"
+    overwrite: bool,
+    /// Folder the paragraph and chapter audio of the book is made in
+    temp_dir: PathBuf,
+}
+
+async fn read_chapter(chapter_number: usize, texts: Vec<String>, options: &BuildOptions) {
+    let tts_format = options.tts_format;
     if texts.len() < 2 {
-        println!("Not enough text to display for chapter {}", chapter_number);
+        say!("Not enough text to display for chapter {}", chapter_number);
         return; // Early exit if there aren't enough texts
     } else {
         if let Some(first_line) = texts.get(0) {
-            println!("{}", first_line.green());
+            say!("{}", first_line.green());
         }
 
         // Print the rest in dark grey (or black)
-        for line in &texts[1..4] {
+        for line in texts.iter().skip(1).take(3) {
             // Adjust the range as needed
-            println!("{}", line.bright_black()); // You can also use line.black() for black color
+            say!("{}", line.bright_black()); // You can also use line.black() for black color
         }
     }
 
     let mut tasks = Vec::new();
-    let pb = ProgressBar::new(texts.len() as u64);
+    // The JSON events replace the bar
+    let pb = if options.progress.is_json() {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(texts.len() as u64)
+    };
     let sty = ProgressStyle::with_template(
         "{spinner:.green} {msg} [{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7}",
     )
@@ -44,25 +122,40 @@ async fn read_chapter(chapter_number: usize, texts: Vec<String>) {
 
     for (i, text) in texts.iter().enumerate() {
         // Use chapter_number in the filename for unique identification
-        let output_file = format!("{}/c{}_p_{}.mp3", AUDIO_OUTPUT_DIR, chapter_number, i + 1);
+        let output_file = format!(
+            "{}/c{}_p_{}.{}",
+            options.temp_dir.display(),
+            chapter_number,
+            i + 1,
+            tts_format.extension()
+        );
 
         let task = task::spawn({
             let text_clone = text.clone();
             let text_preview = text.clone();
             let output_file_clone = output_file.clone();
             let pb_clone = pb.clone(); // Clone the ProgressBar for use in the async block
+            let engine = options.engine.clone();
+            let voice = options.voice.clone();
+            let progress = options.progress.clone();
 
             async move {
-                if let Err(e) = gen_audio(text_clone, output_file_clone).await {
-                    println!("Error generating audio {}", e);
+                let generated =
+                    gen_audio(engine, &voice, text_clone, output_file_clone, tts_format).await;
+                if let Err(e) = generated {
+                    say!("Error generating audio {}", e);
+                    progress.paragraph_failed(i + 1, &e.to_string());
                 } else {
                     match fs::metadata(output_file.clone()) {
                         Ok(metadata) => {
                             let file_size = metadata.len(); // File size in bytes
                             if file_size < 1 {
-                                println!("Empty File delting ({})", text_preview.black());
+                                say!("Empty File delting ({})", text_preview.black());
                                 fs::remove_file(output_file.clone())
                                     .expect("Failed to remove file");
+                                progress.paragraph_failed(i + 1, "The engine returned no audio");
+                            } else {
+                                progress.paragraph_done(i + 1);
                             }
                         }
                         Err(e) => {
@@ -84,7 +177,13 @@ async fn read_chapter(chapter_number: usize, texts: Vec<String>) {
     pb.finish_with_message("All audio files generated!"); // Finish the progress bar
 }
 
-async fn combine_chapter(mut files: Vec<String>, output_file: &str) {
+/// Joins the paragraphs into the chapter file. Returns the loudness after normalization
+/// and, when a transcript is wanted, the number and length (ms) of each joined paragraph.
+async fn combine_chapter(
+    mut files: Vec<String>,
+    output_file: &str,
+    options: &BuildOptions,
+) -> (Option<Loudness>, Vec<(usize, f64)>) {
     files.sort_by_key(|file| {
         let parts: Vec<&str> = file.split('_').collect();
 
@@ -95,7 +194,7 @@ async fn combine_chapter(mut files: Vec<String>, output_file: &str) {
         }
 
         // Try to parse the part as a number; log an error if it fails
-        match parts[2].replace(".mp3", "").parse::<u32>() {
+        match parts[2].split('.').next().unwrap_or("").parse::<u32>() {
             Ok(num) => num,
             Err(_) => {
                 eprintln!("Warning: Unable to parse number from '{}'", parts[2]);
@@ -104,17 +203,57 @@ async fn combine_chapter(mut files: Vec<String>, output_file: &str) {
         }
     });
     if Path::new(output_file).exists() {
-        println!("{output_file} already exists");
+        say!("{output_file} already exists");
+        return (None, Vec::new());
+    }
+
+    // Filters need decoded audio, so post-processing rules out copying the streams
+    let mut source = options.tts_format;
+    let encoder_args = if options.post.is_active() {
+        options.encoder.encode_args(options.output_format)
     } else {
-        concatenate_audio_files(files, output_file); // Ensure you await the async function
+        options.encoder.ffmpeg_args(options.output_format, source)
+    };
+    if options.post.trim_silence {
+        files = postprocess::trim_silence(files);
+        source = TtsFormat::Wav;
     }
+
+    // Measured after trimming, these are exactly the pieces that get joined
+    let mut paragraph_lengths = Vec::new();
+    if !options.transcripts.is_empty() || options.read_along.is_some() {
+        for file in &files {
+            let number = file
+                .split('_')
+                .nth(2)
+                .and_then(|part| part.split('.').next())
+                .and_then(|number| number.parse::<usize>().ok());
+            match (number, ffmpeg::get_audio_length(file)) {
+                (Some(number), Ok(length)) => paragraph_lengths.push((number, length)),
+                (_, Err(e)) => eprintln!("{}", e),
+                (None, _) => {}
+            }
+        }
+    }
+    let loudness = concatenate_audio_files(
+        files,
+        output_file,
+        &encoder_args,
+        source,
+        &options.post,
+        options.pause,
+    );
+    (loudness, paragraph_lengths)
 }
 
-async fn gen_audio(txt: String, output_file: String) -> Result<(), Box<dyn std::error::Error>> {
-    let audio_data = request_audio(
-        &build_ssml(&txt, "en-US-BrianNeural", "medium", "medium", "medium"),
-        "audio-24khz-96kbitrate-mono-mp3",
-    )?;
+async fn gen_audio(
+    engine: Arc<dyn TtsEngine>,
+    voice: &Voice,
+    txt: String,
+    output_file: String,
+    tts_format: TtsFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let audio_data = engine.synthesize(&txt, voice, tts_format)?;
 
     OpenOptions::new()
         .create(true)
@@ -126,7 +265,7 @@ async fn gen_audio(txt: String, output_file: String) -> Result<(), Box<dyn std::
     Ok(())
 }
 
-fn get_files(dir: &Path) -> io::Result<Vec<String>> {
+fn get_files(dir: &Path, extension: &str) -> io::Result<Vec<String>> {
     let mut files = Vec::new(); // Initialize a vector to store file paths
 
     // Iterate over entries in the specified directory
@@ -134,8 +273,11 @@ fn get_files(dir: &Path) -> io::Result<Vec<String>> {
         let entry = entry?; // Handle potential errors when accessing entries
         let path = entry.path(); // Get the path of the entry
 
-        // Check if the entry is a file and ends with .mp3
-        if path.is_file() && path.extension().map_or(false, |ext| ext == "mp3") {
+        // Check if the entry is a paragraph file (c1_p_1.mp3); chapter files can share the extension
+        let is_paragraph = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().contains("_p_"));
+        if path.is_file() && is_paragraph && path.extension().is_some_and(|ext| ext == extension) {
             // Push the path as a String into the vector
             files.push(path.to_string_lossy().to_string());
         }
@@ -147,14 +289,14 @@ fn get_files(dir: &Path) -> io::Result<Vec<String>> {
 fn get_chapter_number(entry: &str) -> Option<u32> {
     if let Some(pos) = entry.find("chapter_") {
         let number_part = &entry[pos + 8..];
-        if let Some(m4a_pos) = number_part.find(".m4a") {
-            return number_part[..m4a_pos].parse::<u32>().ok();
+        if let Some(ext_pos) = number_part.find('.') {
+            return number_part[..ext_pos].parse::<u32>().ok();
         }
     }
     None
 }
 
-fn get_chap_files(dir: &Path) -> io::Result<Vec<String>> {
+fn get_chap_files(dir: &Path, extension: &str) -> io::Result<Vec<String>> {
     let mut files = Vec::new(); // Initialize a vector to store file paths
 
     // Iterate over entries in the specified directory
@@ -162,8 +304,11 @@ fn get_chap_files(dir: &Path) -> io::Result<Vec<String>> {
         let entry = entry?; // Handle potential errors when accessing entries
         let path = entry.path(); // Get the path of the entry
 
-        // Check if the entry is a file and ends with .mp3
-        if path.is_file() && path.extension().map_or(false, |ext| ext == "m4a") {
+        // Check if the entry is a chapter file with the extension of the output format
+        let is_chapter = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with("chapter_"));
+        if path.is_file() && is_chapter && path.extension().is_some_and(|ext| ext == extension) {
             // Push the path as a String into the vector
             files.push(path.to_string_lossy().to_string());
         }
@@ -172,114 +317,2164 @@ fn get_chap_files(dir: &Path) -> io::Result<Vec<String>> {
 
     Ok(files) // Return the vector of file paths
 }
-async fn make_book(book_path: &str, opf_file: &str, cover: &str) {
-    let chapters = read_sections(book_path);
-    let titles = get_titles(book_path);
-    let min_length = chapters.len().min(titles.len());
-    let mut chapter_lengths = Vec::new();
+async fn make_book(
+    book: Book,
+    opf_file: Option<&str>,
+    cover: Option<&str>,
+    options: &BuildOptions,
+) -> Result<Vec<PathBuf>, String> {
+    // A sample is a book of just the selected chapters, a splice keeps them all and
+    // takes the others from the chapter set
+    let mut spliced = None;
+    let book = match &options.chapters {
+        Some(selected) => {
+            let total = book.get_all_chapters().len();
+            selection::check_range(selected, total)?;
+            match &options.splice_into {
+                Some(dir) => {
+                    spliced = Some(splice_set(&book, selected, dir, options.output_format)?);
+                    book
+                }
+                None => book.with_chapters(&selected.0),
+            }
+        }
+        None => book,
+    };
+    // EPUB readers only have to play MP3 and AAC, the check can't wait for the audio
+    if options.read_along.is_some()
+        && !matches!(options.output_format, OutputFormat::M4b | OutputFormat::Mp3)
+    {
+        return Err(format!(
+            "--read-along needs M4B or MP3 output, not {}",
+            enum_name(&options.output_format)
+        ));
+    }
+    if options.dry_run {
+        let estimates = estimate::estimate_book(&book, &options.voice, options.pause);
+        estimate::print_estimates(&estimates, &options.voice);
+        return Ok(Vec::new());
+    }
+    let format = options.output_format;
+    let titles = book.get_titles();
 
-    let mut book = Book::new();
-    for i in 0..min_length {
-        book.add_chapter(&titles[i], chapters[i].clone());
+    // Metadata problems are reported before spending hours on synthesis
+    let mut metadata_map = book_metadata(opf_file, book.get_title(), &options.metadata_overrides);
+    print_warnings(&metadata_map.warnings);
+    if let (Some(selected), None) = (&options.chapters, &spliced) {
+        let chapters = selection::describe(selected);
+        metadata_map.title = format!("{} (chapters {})", metadata_map.title, chapters);
     }
+    let cover = find_cover(cover, opf_file, &metadata_map, options)
+        .and_then(|cover_image| load_cover(&cover_image, &options.cover, &options.temp_dir));
+    let cover = cover.as_ref();
+    let extension = format.audio_extension();
+    let mut chapter_lengths = BTreeMap::new();
+    let mut chapter_loudness = Vec::new();
+    let mut chapter_cues: BTreeMap<usize, Vec<Cue>> = BTreeMap::new();
+    let mut spoken = Vec::new();
+    let mut outputs = Vec::new();
 
-    for (chapter_number, (_, content)) in book.get_all_chapters().iter().enumerate() {
+    let chapters = book.get_all_chapters();
+    let progress = &options.progress;
+    let paragraphs = chapters.iter().map(|(_, content)| content.len()).sum();
+    progress.start_book(&metadata_map.full_title(), chapters.len(), paragraphs);
+    for (chapter_number, (_, content)) in chapters.iter().enumerate() {
+        let title = titles.get(chapter_number).map(String::as_str).unwrap_or("");
+        progress.start_chapter(chapter_number + 1, title, content.len());
+        let output_file = format!(
+            "{}/chapter_{}.{}",
+            options.temp_dir.display(),
+            chapter_number,
+            extension
+        );
+        let selected = options
+            .chapters
+            .as_ref()
+            .is_some_and(|selected| selected.contains(chapter_number + 1));
+        let kept = spliced
+            .as_ref()
+            .filter(|_| !selected)
+            .and_then(|set| set.get(&(chapter_number + 1)));
+        if let Some(kept) = kept {
+            // Kept as it was, combine_chapter leaves an existing chapter file alone
+            fs::copy(kept, &output_file)
+                .map_err(|e| format!("Failed to copy {}: {}", kept.display(), e))?;
+        }
+        progress.set_stage("synthesizing");
         if !Path::new(&format!(
-            "{}/chapter_{}.m4a",
-            AUDIO_OUTPUT_DIR, chapter_number
+            "{}/chapter_{}.{}",
+            options.temp_dir.display(),
+            chapter_number,
+            extension
         ))
         .exists()
         {
-            read_chapter(chapter_number + 1, content.to_vec()).await; // Pass chapter number
+            read_chapter(chapter_number + 1, content.to_vec(), options).await; // Pass chapter number
         } else {
-            println!("Chapter already processed");
+            say!("Chapter already processed");
         }
 
         let mut file_paths = Vec::new();
-        let dir = Path::new(AUDIO_OUTPUT_DIR);
-        match get_files(dir) {
+        match get_files(&options.temp_dir, options.tts_format.extension()) {
             Ok(files) => {
                 file_paths = files;
             }
             Err(e) => eprintln!("Error reading directory: {}", e), // Handle potential errors
         }
 
-        let output_file = format!("{}/chapter_{}.m4a", AUDIO_OUTPUT_DIR, chapter_number);
-        combine_chapter(file_paths, &output_file).await;
+        progress.set_stage("combining");
+        let (loudness, paragraph_lengths) =
+            combine_chapter(file_paths, &output_file, options).await;
+        if let Some(loudness) = loudness {
+            chapter_loudness.push((titles[chapter_number].clone(), loudness));
+        }
         match ffmpeg::get_audio_length(&output_file) {
-            Ok(length) => chapter_lengths.push(length),
-            Err(e) => println!("{}", e),
+            Ok(length) => {
+                progress.chapter_finished(chapter_number + 1, title, length);
+                chapter_lengths.insert(chapter_number, length);
+                chapter_cues.insert(
+                    chapter_number,
+                    transcript::chapter_cues(content, &paragraph_lengths, options.pause * 1000.0),
+                );
+                let silence = options.pause * 1000.0;
+                for (number, start, end) in transcript::paragraph_times(&paragraph_lengths, silence)
+                {
+                    if let Some(text) = number.checked_sub(1).and_then(|i| content.get(i)) {
+                        spoken.push(SpokenParagraph {
+                            text: text.clone(),
+                            audio_file: output_file.clone(),
+                            start,
+                            end,
+                        });
+                    }
+                }
+            }
+            Err(e) => say!("{}", e),
         }
     }
 
-    let chapter_file = format!("{}/chapter.txt", AUDIO_OUTPUT_DIR);
-    let chap_titles: Vec<&str> = titles.iter().map(|s| s.as_str()).collect();
+    let mut found_files = match get_chap_files(&options.temp_dir, extension) {
+        Ok(files) => files,
+        Err(e) => return Err(format!("Failed to get chapter files: {}", e)),
+    };
+    found_files.sort_by_key(|entry| get_chapter_number(entry).unwrap_or(u32::MAX));
 
-    match ffmpeg::create_chapter_file(chapter_lengths, chap_titles, chapter_file.clone()) {
-        Ok(()) => println!("Chapter file created successfully"),
-        Err(e) => panic!("Failed to create chapter file: {}", e),
+    // Chapters too short to be read have no file, the others keep the number of their
+    // book chapter so titles, lengths (and --splice-into) find the right one
+    let mut chapter_files = Vec::new();
+    let mut made_chapters: Vec<(usize, String)> = Vec::new();
+    let mut made_lengths = Vec::new();
+    for (i, file) in found_files.into_iter().enumerate() {
+        let index = get_chapter_number(&file).map_or(i, |number| number as usize);
+        let Some(&length) = chapter_lengths.get(&index) else {
+            // Its chapter marks would be wrong, and the ones after it too
+            say!(
+                "{}",
+                format!("Leaving out {}, its length could not be read", file).yellow()
+            );
+            fs::remove_file(&file).ok();
+            continue;
+        };
+        let title = titles
+            .get(index)
+            .cloned()
+            .unwrap_or_else(|| format!("Chapter {}", index + 1));
+        chapter_files.push(file);
+        made_chapters.push((index, title));
+        made_lengths.push(length);
+    }
+    if chapter_files.is_empty() {
+        if let Some(cover) = cover {
+            fs::remove_file(&cover.path).ok();
+        }
+        return Err("No chapter audio was generated".to_string());
     }
+    let sidecar_chapters: Vec<(&str, f64)> = made_chapters
+        .iter()
+        .map(|(_, title)| title.as_str())
+        .zip(made_lengths.iter().copied())
+        .collect();
 
-    let audio_path = PathBuf::from(AUDIO_OUTPUT_DIR);
-    let mut chapter_files = match get_chap_files(&audio_path) {
-        Ok(files) => files,
-        Err(e) => panic!("Failed to get chapter files: {}", e),
-    };
-    chapter_files.sort_by_key(|entry| get_chapter_number(entry).unwrap_or(u32::MAX));
+    if !chapter_loudness.is_empty() {
+        print_loudness(&chapter_loudness);
+    }
+    progress.set_stage("writing");
+
+    // The read-along EPUB embeds the chapter audio, so it's made before the chapters go
+    if let Some(source_epub) = &options.read_along {
+        let naming = book_naming(options);
+        if let Some(epub_file) = book_path(&naming, &metadata_map, "epub", options.overwrite) {
+            let written = written_path(Path::new(&epub_file), options.overwrite);
+            let result = overlay::write_read_along(source_epub, &spoken, &written)
+                .and_then(|linked| replace_with(&written, &epub_file).map(|_| linked));
+            match result {
+                Ok(linked) => {
+                    say!(
+                        "{}",
+                        format!(
+                            "Read-along EPUB written to {} ({} passages)",
+                            epub_file, linked
+                        )
+                        .green()
+                    );
+                    progress.output(&epub_file);
+                    outputs.push(PathBuf::from(epub_file));
+                }
+                Err(e) => say!("{}", e.red()),
+            }
+        }
+    }
+
+    // One file per chapter doesn't need the chapters joined into a single book
+    if format == OutputFormat::Chapters {
+        let mut output_dir = options.naming.path_for(&metadata_map);
+        let mut written_dir = output_dir.clone();
+        if options.overwrite {
+            // Written to "Title.new" first, the old set stays if that fails
+            written_dir.as_mut_os_string().push(".new");
+            fs::remove_dir_all(&written_dir).ok();
+        } else {
+            output_dir = naming::unique_path(&output_dir, "");
+            written_dir = output_dir.clone();
+        }
+        metdata::tag_chapter_files(
+            &chapter_files,
+            &made_chapters,
+            &written_dir,
+            &metadata_map,
+            cover,
+        );
+        if options.sidecars {
+            write_sidecars(&written_dir, &metadata_map, &sidecar_chapters, cover);
+        }
+        // Every chapter with a length has its cues
+        for (index, title) in &made_chapters {
+            let chapter_path = written_dir.join(metdata::chapter_file_name(*index, title));
+            write_transcripts(
+                &chapter_path,
+                &chapter_cues[index],
+                &metadata_map,
+                &options.transcripts,
+            );
+        }
+        for file in chapter_files {
+            fs::remove_file(file).ok();
+        }
+        if let Some(cover) = cover {
+            fs::remove_file(&cover.path).ok();
+        }
+        if options.overwrite {
+            let written =
+                fs::read_dir(&written_dir).is_ok_and(|mut entries| entries.next().is_some());
+            if !written {
+                fs::remove_dir_all(&written_dir).ok();
+                return Err("The chapter files could not be written".to_string());
+            }
+            if output_dir.exists() {
+                fs::remove_dir_all(&output_dir)
+                    .map_err(|e| format!("Failed to remove {}: {}", output_dir.display(), e))?;
+            }
+            replace_with(
+                &written_dir.to_string_lossy(),
+                &output_dir.to_string_lossy(),
+            )?;
+        }
+        progress.output(&output_dir.to_string_lossy());
+        outputs.push(output_dir);
+        return Ok(outputs);
+    }
+
+    // Books over the size or duration limit are split at chapter boundaries
+    let chapter_sizes: Vec<u64> = chapter_files
+        .iter()
+        .map(|file| fs::metadata(file).map(|m| m.len()).unwrap_or(0))
+        .collect();
+    let parts = split::plan_parts(&made_lengths, &chapter_sizes, &options.split);
+    if parts.len() > 1 {
+        say!(
+            "{}",
+            format!("Splitting book into {} parts", parts.len()).yellow()
+        );
+    }
+
+    let naming = book_naming(options);
+    let mut book_dir = None;
+    for (part_index, range) in parts.iter().enumerate() {
+        let chapter_file = format!("{}/chapter.txt", options.temp_dir.display());
+        let part_titles: Vec<&str> = made_chapters[range.clone()]
+            .iter()
+            .map(|(_, title)| title.as_str())
+            .collect();
+        let part_lengths = made_lengths[range.clone()].to_vec();
+
+        match ffmpeg::create_chapter_file(part_lengths, part_titles.clone(), chapter_file.clone()) {
+            Ok(()) => say!("Chapter file created successfully"),
+            Err(e) => return Err(format!("Failed to create chapter file: {}", e)),
+        }
+
+        let part_files = chapter_files.get(range.clone()).unwrap_or(&[]).to_vec();
+        let output_file = format!(
+            "{}/book_{}.{}",
+            options.temp_dir.display(),
+            part_index + 1,
+            extension
+        );
+        progress.set_stage("joining");
+        ffmpeg::add_chapter_data(&chapter_file, part_files, &output_file).ok();
+
+        // Every part keeps the book as album and gets its own title and disc number
+        let mut part_metadata = metadata_map.clone();
+        if parts.len() > 1 {
+            part_metadata.part = Some((part_index + 1, parts.len()));
+        }
+        let Some(book_file) = book_path(
+            &naming,
+            &part_metadata,
+            format.file_extension(),
+            options.overwrite,
+        ) else {
+            continue;
+        };
+        progress.set_stage("tagging");
+        let written = written_path(Path::new(&book_file), options.overwrite);
+        metdata::add_metadata(&output_file, &written, &part_metadata, cover, format);
+        if options.overwrite {
+            // Without a new file the old book stays, and isn't reported as made
+            if !Path::new(&written).exists() {
+                continue;
+            }
+            if let Err(e) = replace_with(&written, &book_file) {
+                say!("{}", e.red());
+                continue;
+            }
+        }
+
+        // Cue times restart with every part
+        if !options.transcripts.is_empty() {
+            let marks = ffmpeg::chapter_marks(&made_lengths[range.clone()], &part_titles);
+            let cues: Vec<Cue> = made_chapters[range.clone()]
+                .iter()
+                .zip(&marks)
+                .flat_map(|((index, _), (_, start, _))| {
+                    chapter_cues[index].iter().map(|cue| cue.offset(*start))
+                })
+                .collect();
+            write_transcripts(
+                Path::new(&book_file),
+                &cues,
+                &part_metadata,
+                &options.transcripts,
+            );
+        }
+        book_dir = Path::new(&book_file).parent().map(Path::to_path_buf);
+        if Path::new(&book_file).exists() {
+            progress.output(&book_file);
+            outputs.push(PathBuf::from(book_file));
+        }
+    }
+
+    // One set of sidecars describes all parts, with chapter times running across them
+    if let Some(book_dir) = book_dir.filter(|_| options.sidecars) {
+        write_sidecars(&book_dir, &metadata_map, &sidecar_chapters, cover);
+    }
 
-    let output_file = format!("{}/book.m4a", AUDIO_OUTPUT_DIR);
-    ffmpeg::add_chapter_data(&chapter_file, chapter_files.to_vec(), &output_file).ok();
     for file in chapter_files {
-        println!("Trying to remove file");
+        say!("Trying to remove file");
         fs::remove_file(file).ok();
     }
+    if let Some(cover) = cover {
+        fs::remove_file(&cover.path).ok();
+    }
+    if outputs.is_empty() {
+        return Err("The audiobook could not be written".to_string());
+    }
+    Ok(outputs)
+}
+
+/// Reads the book in the given (or detected) input format and makes the audiobook,
+/// returning the files and folders written
+async fn build_book(
+    file_path: &str,
+    input_format: Option<&str>,
+    opf_file: Option<&str>,
+    cover: Option<&str>,
+    options: &BuildOptions,
+) -> Result<Vec<PathBuf>, String> {
+    let book = read_book_file(file_path, input_format)?;
+    let result = make_book(book, opf_file, cover, options).await;
+    options.progress.finished(&result);
+    result
+}
+
+fn read_book_file(file_path: &str, input_format: Option<&str>) -> Result<Book, String> {
+    let Some(format) = input::find_format(file_path, input_format) else {
+        return Err(format!(
+            "Can't read {}: expected one of: {} (or pass --input-format)",
+            file_path,
+            input::format_names().join(", ")
+        ));
+    };
+    let mut book = format
+        .read_book(Path::new(file_path))
+        .map_err(|e| format!("Failed to read {} file: {}", format.name(), e))?;
+    // Without a title in the document the file name has to do
+    if book.get_title().is_none() {
+        if let Some(stem) = Path::new(file_path).file_stem() {
+            book.set_title(&stem.to_string_lossy());
+        }
+    }
+    Ok(book)
+}
+
+// The OPF metadata with the overrides applied and the gaps filled in
+fn book_metadata(
+    opf_file: Option<&str>,
+    book_title: Option<&str>,
+    overrides: &[(String, String)],
+) -> BookMetadata {
+    let mut metadata = match opf_file {
+        Some(opf_file) => metdata::get_metadata(opf_file),
+        None => BookMetadata::default(),
+    };
+    apply_overrides(&mut metadata, overrides);
+    metadata.fill_missing(book_title);
+    metadata
+}
+
+// The chapter set the selected chapters are spliced into. Chapters too short to be read
+// have no file in it, every other chapter that isn't selected needs one.
+fn splice_set(
+    book: &Book,
+    selected: &selection::Selection,
+    dir: &Path,
+    format: OutputFormat,
+) -> Result<BTreeMap<usize, PathBuf>, String> {
+    if !matches!(format, OutputFormat::M4b | OutputFormat::Chapters) {
+        return Err(
+            "--splice-into needs --output-format m4b or chapters, chapter sets are .m4a"
+                .to_string(),
+        );
+    }
+    let chapters = book.get_all_chapters();
+    let set = selection::chapter_set(dir, format.audio_extension(), chapters.len())?;
+    let missing = chapters.iter().enumerate().position(|(i, (_, content))| {
+        !selected.contains(i + 1) && content.len() >= 2 && !set.contains_key(&(i + 1))
+    });
+    match missing {
+        Some(i) => Err(format!(
+            "{} has no file for chapter {}",
+            dir.display(),
+            i + 1
+        )),
+        None => Ok(set),
+    }
+}
+
+// Without --cover the OPF's cover is used, and failing that one is generated
+fn find_cover(
+    cover_image: Option<&str>,
+    opf_file: Option<&str>,
+    metadata: &BookMetadata,
+    options: &BuildOptions,
+) -> Option<String> {
+    if let Some(cover_image) = cover_image {
+        return Some(cover_image.to_string());
+    }
+    if let Some(opf_file) = opf_file {
+        if let Some(opf_cover) = metdata::find_cover(opf_file) {
+            say!("Using cover from OPF: {}", opf_cover);
+            return Some(opf_cover);
+        }
+    }
+    if options.cover_template == CoverTemplate::None {
+        return None;
+    }
+
+    let generated = format!("{}/generated_cover.png", options.temp_dir.display());
+    let author = metadata.authors.first().map(String::as_str);
+    match cover::generate_cover(&metadata.title, author, options.cover_template, &generated) {
+        Ok(()) => {
+            say!("{}", "Generated a cover from the title and author".yellow());
+            Some(generated)
+        }
+        Err(e) => {
+            say!("{}", format!("Warning: {}", e).yellow());
+            None
+        }
+    }
+}
+
+// An unreadable cover is reported and the book is made without one
+fn load_cover(cover_image: &str, settings: &CoverSettings, dir: &Path) -> Option<Cover> {
+    match cover::prepare_cover(cover_image, settings, dir) {
+        Ok(cover) => Some(cover),
+        Err(e) => {
+            say!(
+                "{}",
+                format!("Warning: {}, continuing without a cover", e).yellow()
+            );
+            None
+        }
+    }
+}
+
+// The sidecars describe a single book, so with them each book gets a folder of its own.
+// A chapter set is a folder already.
+fn book_naming(options: &BuildOptions) -> OutputNaming {
+    if options.sidecars && options.output_format != OutputFormat::Chapters {
+        options.naming.with_book_folder()
+    } else {
+        options.naming.clone()
+    }
+}
+
+// Fills in the name template and creates the folders. An existing book is never
+// overwritten unless `overwrite` is set, then the path is the one the template gives.
+fn book_path(
+    naming: &OutputNaming,
+    metadata: &BookMetadata,
+    extension: &str,
+    overwrite: bool,
+) -> Option<String> {
+    let mut path = naming.path_for(metadata);
+    if overwrite {
+        path.as_mut_os_string().push(format!(".{}", extension));
+    } else {
+        path = naming::unique_path(&path, extension);
+    }
+    if let Some(parent) = path.parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            let message = format!("Failed to create folder {}: {}", parent.display(), e);
+            say!("{}", message.red());
+            return None;
+        }
+    }
+    Some(path.to_string_lossy().to_string())
+}
 
-    let metadata_map = metdata::get_metadata(opf_file);
-    metdata::add_metadata(&output_file, &metadata_map, &cover);
+// A book made again is written to "Title.new.m4b" first, so the old one stays if that fails
+fn written_path(path: &Path, overwrite: bool) -> String {
+    if !overwrite {
+        return path.to_string_lossy().to_string();
+    }
+    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+    name.push(".new");
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    let written = path.with_file_name(name);
+    fs::remove_file(&written).ok();
+    written.to_string_lossy().to_string()
+}
+
+// Moves the new file over the old one
+fn replace_with(written: &str, path: &str) -> Result<(), String> {
+    if written == path {
+        return Ok(());
+    }
+    fs::rename(written, path).map_err(|e| format!("Failed to replace {}: {}", path, e))
+}
+
+// `chapters` are the title and length of every chapter in the audio, in order
+fn write_sidecars(
+    dir: &Path,
+    metadata: &BookMetadata,
+    chapters: &[(&str, f64)],
+    cover: Option<&Cover>,
+) {
+    let (names, lengths): (Vec<&str>, Vec<f64>) = chapters.iter().copied().unzip();
+    let chapters = ffmpeg::chapter_marks(&lengths, &names);
+    match metdata::write_sidecars(dir, metadata, &chapters, cover) {
+        Ok(()) => say!("Sidecar files written to {}", dir.display()),
+        Err(e) => say!("{}", e.red()),
+    }
+}
+
+// One transcript per format, named after the audio file
+fn write_transcripts(
+    audio_file: &Path,
+    cues: &[Cue],
+    metadata: &BookMetadata,
+    formats: &[TranscriptFormat],
+) {
+    if cues.is_empty() {
+        return;
+    }
+    let author = metadata.authors.first().map(String::as_str);
+    for format in formats {
+        let path = audio_file.with_extension(format.extension());
+        match transcript::write_transcript(&path, *format, cues, &metadata.full_title(), author) {
+            Ok(()) => say!("Transcript written to {}", path.display()),
+            Err(e) => say!(
+                "{}",
+                format!("Failed to write {}: {}", path.display(), e).red()
+            ),
+        }
+    }
+}
+
+fn print_warnings(warnings: &[String]) {
+    for warning in warnings {
+        say!("{}", format!("Warning: {}", warning).yellow());
+    }
+}
+
+// Loudness of every chapter after normalization, so it ends up in the job log
+fn print_loudness(chapter_loudness: &[(String, Loudness)]) {
+    say!("{}", "Loudness after normalization:".green());
+    for (title, loudness) in chapter_loudness {
+        say!(
+            "  {}: {:.1} LUFS, true peak {:.1} dBTP, range {:.1} LU",
+            title,
+            loudness.integrated,
+            loudness.true_peak,
+            loudness.range
+        );
+    }
 }
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// file
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Args, Debug)]
+struct BookArgs {
+    /// The book to read
+    file: String,
+
+    /// OPF file with the book's metadata
+    #[arg(short, long)]
+    opf: Option<String>,
+
+    /// Cover image; without it the OPF cover is used, or one is generated
+    #[arg(short, long)]
+    cover: Option<String>,
+
+    /// Input format (txt, gutenberg, html, fb2, docx); detected from the file when omitted
+    #[arg(long)]
+    input_format: Option<String>,
+
+    #[command(flatten)]
+    build: BuildArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct ExtractArgs {
+    /// The EPUB to extract
+    file: String,
+
+    /// Where to write the text; the cover and content.opf go next to it
+    #[arg(short, long, default_value = "book.txt")]
+    output: PathBuf,
+
+    /// Leave out chapters whose title contains this, besides copyright pages, landmarks
+    /// and tables of contents (repeatable)
+    #[arg(long)]
+    skip_chapter: Vec<String>,
+
+    /// Profile of edgeab.toml to take skip_chapter from, instead of its default one
+    #[arg(long)]
+    profile: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct InspectArgs {
+    /// A book in any supported format, or an audiobook
+    file: String,
+
+    /// Input format (txt, gutenberg, html, fb2, docx); detected from the file when omitted
+    #[arg(long)]
+    input_format: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct ValidateArgs {
+    /// The book to check
+    file: String,
+
+    /// OPF file with the book's metadata
+    #[arg(short, long)]
+    opf: Option<String>,
+
+    /// Cover image
     #[arg(short, long)]
+    cover: Option<String>,
+
+    /// Input format (txt, gutenberg, html, fb2, docx); detected from the file when omitted
+    #[arg(long)]
+    input_format: Option<String>,
+}
+
+// Options for turning a book into an audiobook, shared by the single book and batch modes
+#[derive(clap::Args, Debug)]
+struct BuildArgs {
+    /// Override a metadata field, e.g. --meta narrator="Jane Doe" (repeatable)
+    #[arg(long = "meta", value_parser = metdata::parse_meta_arg)]
+    meta: Vec<(String, String)>,
+
+    /// TOML or JSON file with metadata overrides, applied before --meta
+    #[arg(long)]
+    meta_file: Option<String>,
+
+    /// Output format of the finished audiobook
+    #[arg(long, value_enum, default_value_t = OutputFormat::M4b)]
+    output_format: OutputFormat,
+
+    /// FFmpeg audio encoder, or "copy" to keep the backend stream (default depends on the output format)
+    #[arg(long)]
+    codec: Option<String>,
+
+    /// Audio bitrate such as 64k (default depends on the output format)
+    #[arg(long)]
+    bitrate: Option<String>,
+
+    /// Output sample rate in Hz
+    #[arg(long)]
+    sample_rate: Option<u32>,
+
+    /// Number of output channels
+    #[arg(long)]
+    channels: Option<u32>,
+
+    /// Stream requested from edge-tts; wav avoids encoding the audio twice
+    #[arg(long, value_enum, default_value_t = TtsFormat::Mp3)]
+    tts_format: TtsFormat,
+
+    /// Normalize every chapter to this integrated loudness in LUFS (EBU R128), e.g. -18
+    #[arg(long, allow_negative_numbers = true)]
+    loudness: Option<f64>,
+
+    /// Trim leading and trailing silence from each paragraph
+    #[arg(long)]
+    trim_silence: bool,
+
+    /// Apply light dynamic range compression
+    #[arg(long)]
+    compress: bool,
+
+    /// Split the book into parts no longer than this (e.g. 10h, 90m, 12:30:00)
+    #[arg(long, value_parser = split::parse_duration)]
+    max_part_duration: Option<f64>,
+
+    /// Split the book into parts no larger than this (e.g. 4G, 700M)
+    #[arg(long, value_parser = split::parse_size)]
+    max_part_size: Option<u64>,
+
+    #[command(flatten)]
+    cover_settings: CoverSettings,
+
+    /// Folder the audiobook is written to
+    #[arg(long, default_value = ".")]
+    output: PathBuf,
+
+    /// File name template, folders separated by '/'. Placeholders: {title}, {subtitle},
+    /// {author}, {narrator}, {series}, {series_index}, {part}, {year}, {language},
+    /// {publisher}, {isbn}
+    #[arg(long, default_value = naming::DEFAULT_TEMPLATE)]
+    name_template: String,
+
+    /// Also write metadata.json, metadata.abs, desc.txt, reader.txt and the cover
+    /// next to the audiobook for Audiobookshelf and Plex. Each book then goes in a
+    /// folder of its own, a {title} folder is added if the template has none.
+    #[arg(long)]
+    sidecars: bool,
+
+    /// Write a transcript with sentence timings next to the audio (srt, vtt, lrc; comma separated)
+    #[arg(long, value_enum, value_delimiter = ',')]
+    transcript: Vec<TranscriptFormat>,
+
+    /// The EPUB the text was extracted from; a copy with media overlays linking its
+    /// paragraphs to the audio is written next to the audiobook (needs m4b or mp3 output)
+    #[arg(long)]
+    read_along: Option<String>,
+
+    /// Layout of the cover generated when no cover image is given or found in the OPF
+    #[arg(long, value_enum, default_value_t = CoverTemplate::Band)]
+    cover_template: CoverTemplate,
+
+    /// Leave out EPUB chapters whose title contains this, besides copyright pages, landmarks
+    /// and tables of contents (repeatable)
+    #[arg(long)]
+    skip_chapter: Vec<String>,
+
+    /// Seconds of silence between paragraphs
+    #[arg(long, default_value_t = ffmpeg::PARAGRAPH_SILENCE)]
+    pause: f64,
+
+    /// Voice to read the book with
+    #[arg(long, default_value = "en-US-BrianNeural")]
+    voice: String,
+
+    /// Speaking rate: x-slow, slow, medium, fast, x-fast or relative such as +10% or -5%
+    #[arg(long, default_value = "medium", allow_hyphen_values = true)]
+    rate: String,
+
+    /// Speech synthesis backend
+    #[arg(long, value_enum, default_value_t = EngineKind::Edge)]
+    engine: EngineKind,
+
+    /// Profile of edgeab.toml to take the settings from, instead of its default one
+    #[arg(long)]
+    profile: Option<String>,
+
+    /// Show progress as bars, or as newline-delimited JSON events for other programs
+    /// (on stdout with the other messages on stderr, or in --progress-file)
+    #[arg(long, value_enum, default_value_t = ProgressMode::Bar)]
+    progress: ProgressMode,
+
+    /// File the JSON events are written to instead of stdout
+    #[arg(long)]
+    progress_file: Option<PathBuf>,
+
+    /// Only print the chapters with their size and estimated duration, without synthesizing
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Synthesize only these chapters, e.g. 3,5-7. Without --splice-into they make a
+    /// standalone sample
+    #[arg(long, value_parser = selection::parse_chapters)]
+    chapters: Option<selection::Selection>,
+
+    /// Folder of an earlier --output-format chapters run; the selected chapters replace
+    /// theirs and the whole book is put together again
+    #[arg(long, requires = "chapters")]
+    splice_into: Option<PathBuf>,
+}
+
+impl BuildArgs {
+    /// The options edgeab.toml can set, with their current values
+    fn settings(&self) -> Vec<(&'static str, String)> {
+        let optional = |value: Option<String>| value.unwrap_or_default();
+        let list = |values: &[String]| values.join(", ");
+        vec![
+            ("voice", self.voice.clone()),
+            ("rate", self.rate.clone()),
+            ("engine", enum_name(&self.engine)),
+            ("pause", self.pause.to_string()),
+            ("output_format", enum_name(&self.output_format)),
+            ("tts_format", enum_name(&self.tts_format)),
+            ("codec", optional(self.codec.clone())),
+            ("bitrate", optional(self.bitrate.clone())),
+            (
+                "sample_rate",
+                optional(self.sample_rate.map(|rate| rate.to_string())),
+            ),
+            (
+                "channels",
+                optional(self.channels.map(|count| count.to_string())),
+            ),
+            (
+                "loudness",
+                optional(self.loudness.map(|lufs| lufs.to_string())),
+            ),
+            ("trim_silence", self.trim_silence.to_string()),
+            ("compress", self.compress.to_string()),
+            ("output", self.output.display().to_string()),
+            ("name_template", self.name_template.clone()),
+            ("sidecars", self.sidecars.to_string()),
+            (
+                "transcript",
+                self.transcript
+                    .iter()
+                    .map(enum_name)
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+            ("skip_chapter", list(&self.skip_chapter)),
+            ("cover_template", enum_name(&self.cover_template)),
+        ]
+    }
+
+    /// Takes the settings of the config files that weren't given on the command line
+    fn apply_config(
+        &mut self,
+        config: &config::Config,
+        matches: &ArgMatches,
+    ) -> Result<(), String> {
+        let known: Vec<&str> = self.settings().iter().map(|(key, _)| *key).collect();
+        for setting in &config.settings {
+            let key = match setting.key.as_str() {
+                "skip_chapters" => "skip_chapter",
+                "transcripts" => "transcript",
+                key => key,
+            };
+            if !known.contains(&key) {
+                return Err(format!(
+                    "{}: unknown setting {}, expected one of: {}",
+                    setting.origin.display(),
+                    setting.key,
+                    known.join(", ")
+                ));
+            }
+            if matches.value_source(key) == Some(ValueSource::CommandLine) {
+                continue;
+            }
+            match key {
+                "voice" => self.voice = setting.string()?,
+                "rate" => self.rate = setting.string()?,
+                "engine" => self.engine = config_enum(setting, &setting.string()?)?,
+                "pause" => self.pause = setting.number(0.0, 60.0)?,
+                "output_format" => self.output_format = config_enum(setting, &setting.string()?)?,
+                "tts_format" => self.tts_format = config_enum(setting, &setting.string()?)?,
+                "codec" => self.codec = Some(setting.string()?),
+                "bitrate" => self.bitrate = Some(setting.string()?),
+                "sample_rate" => self.sample_rate = Some(setting.whole_number(8000, 192_000)?),
+                "channels" => self.channels = Some(setting.whole_number(1, 8)?),
+                // The range FFmpeg's loudnorm takes
+                "loudness" => self.loudness = Some(setting.number(-70.0, -5.0)?),
+                "trim_silence" => self.trim_silence = setting.flag()?,
+                "compress" => self.compress = setting.flag()?,
+                "output" => self.output = PathBuf::from(setting.string()?),
+                "name_template" => self.name_template = setting.string()?,
+                "sidecars" => self.sidecars = setting.flag()?,
+                "transcript" => {
+                    self.transcript = setting
+                        .strings()?
+                        .iter()
+                        .map(|format| config_enum(setting, format))
+                        .collect::<Result<_, _>>()?
+                }
+                "skip_chapter" => self.skip_chapter = setting.strings()?,
+                "cover_template" => self.cover_template = config_enum(setting, &setting.string()?)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn build_options(self) -> Result<BuildOptions, String> {
+        let metadata_overrides = collect_overrides(self.meta_file.as_deref(), &self.meta)?;
+        let events: Option<Box<dyn Write + Send>> = match (self.progress, &self.progress_file) {
+            (ProgressMode::Bar, _) => None,
+            (ProgressMode::Json, Some(path)) => {
+                let file = fs::File::create(path)
+                    .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+                Some(Box::new(file))
+            }
+            (ProgressMode::Json, None) => Some(progress::stdout_events()),
+        };
+        Ok(BuildOptions {
+            output_format: self.output_format,
+            encoder: EncoderSettings {
+                codec: self.codec,
+                bitrate: self.bitrate,
+                sample_rate: self.sample_rate,
+                channels: self.channels,
+            },
+            tts_format: self.tts_format,
+            post: PostProcess {
+                loudness: self.loudness,
+                trim_silence: self.trim_silence,
+                compress: self.compress,
+            },
+            split: SplitLimits {
+                max_duration: self.max_part_duration,
+                max_size: self.max_part_size,
+            },
+            naming: OutputNaming {
+                dir: self.output,
+                template: self.name_template,
+            },
+            sidecars: self.sidecars,
+            transcripts: self.transcript,
+            read_along: self.read_along,
+            cover: self.cover_settings,
+            cover_template: self.cover_template,
+            metadata_overrides,
+            engine: self.engine.engine(),
+            voice: Voice {
+                name: self.voice,
+                rate: self.rate,
+            },
+            progress: Arc::new(Progress::new(events)),
+            dry_run: self.dry_run,
+            pause: self.pause,
+            skip_chapters: self.skip_chapter,
+            chapters: self.chapters,
+            splice_into: self.splice_into,
+            overwrite: false,
+            temp_dir: PathBuf::from(AUDIO_OUTPUT_DIR),
+        })
+    }
+}
+
+// The name clap shows for a value
+fn enum_name<T: ValueEnum>(value: &T) -> String {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default()
+}
+
+fn config_enum<T: ValueEnum>(setting: &config::Setting, text: &str) -> Result<T, String> {
+    T::from_str(text, true).map_err(|_| {
+        let names: Vec<String> = T::value_variants().iter().map(enum_name).collect();
+        format!(
+            "{}: {} can't be \"{}\", expected one of: {}",
+            setting.origin.display(),
+            setting.key,
+            text,
+            names.join(", ")
+        )
+    })
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Write the text of an EPUB to an editable book.txt, with its cover and OPF
+    Extract(ExtractArgs),
+    /// Make the audiobook from a text file (txt, gutenberg, html, fb2, docx), such as an
+    /// edited book.txt
+    Build(BookArgs),
+    /// Make the audiobook from any book, EPUBs included, without the editing step
+    Convert(BookArgs),
+    /// Show the chapters and metadata of a book, or the tags and chapters of an audiobook
+    Inspect(InspectArgs),
+    /// Check that a book, its OPF and its cover are ready to convert
+    Validate(ValidateArgs),
+    /// Rewrite the metadata and cover of an existing .m4b without re-synthesizing it
+    Tag(TagArgs),
+    /// Convert every book in a folder, CSV file or list, skipping those already converted
+    Batch(BatchArgs),
+    /// Keep converting the books dropped into an inbox folder
+    Watch(WatchArgs),
+    /// Run an HTTP API that queues uploaded books and serves the audiobooks
+    Serve(ServeArgs),
+    /// Write a podcast feed (RSS 2.0 with iTunes and Podcasting 2.0 tags) for a folder of audiobooks
+    Feed(FeedArgs),
+    /// Read the start of a book with several voices and rates, to pick a narrator
+    Sample(SampleArgs),
+    /// Work with the edgeab.toml config files
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the settings in effect and where each one comes from: the command line,
+    /// a config file or the default
+    Show(ShowConfigArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct ShowConfigArgs {
+    #[command(flatten)]
+    build: BuildArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct TagArgs {
+    /// The audiobook to retag
     file: String,
 
+    /// Take the metadata from this OPF instead of the file's current tags
+    #[arg(short, long)]
+    opf: Option<String>,
+
+    /// New cover image
+    #[arg(short, long)]
+    cover: Option<String>,
+
+    /// Override a metadata field, e.g. --meta author="Jane Doe" (repeatable)
+    #[arg(long = "meta", value_parser = metdata::parse_meta_arg)]
+    meta: Vec<(String, String)>,
+
+    /// TOML or JSON file with metadata overrides, applied before --meta
+    #[arg(long)]
+    meta_file: Option<String>,
+
+    #[command(flatten)]
+    cover_settings: CoverSettings,
+}
+
+#[derive(clap::Args, Debug)]
+struct FeedArgs {
+    /// Folder with the finished audiobooks or chapter files; searched recursively
+    dir: PathBuf,
+
+    /// URL the folder is served from, e.g. https://example.com/books/dune
+    #[arg(long)]
+    base_url: String,
+
+    /// Where to write the feed [default: <dir>/feed.xml]
+    #[arg(long)]
+    output: Option<PathBuf>,
+
+    /// Take the podcast title, author and description from this OPF instead of the first file's tags
     #[arg(short, long)]
     opf: Option<String>,
+
+    /// Podcast artwork; copied into the folder. Without it a cover.jpg/png in the folder is used
     #[arg(short, long)]
     cover: Option<String>,
+
+    /// Override a metadata field of the podcast, e.g. --meta title="Staff Reading" (repeatable)
+    #[arg(long = "meta", value_parser = metdata::parse_meta_arg)]
+    meta: Vec<(String, String)>,
+
+    #[command(flatten)]
+    cover_settings: CoverSettings,
+}
+
+#[derive(clap::Args, Debug)]
+struct SampleArgs {
+    /// The book to read from; EPUB or any format --input-format takes
+    file: String,
+
+    /// Input format (txt, gutenberg, html, fb2, docx); detected from the file when omitted
+    #[arg(long)]
+    input_format: Option<String>,
+
+    /// Voices to try, comma separated
+    #[arg(long, value_delimiter = ',', default_value = "en-US-BrianNeural")]
+    voices: Vec<String>,
+
+    /// Rates to try with every voice, comma separated, e.g. medium,+10%
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "medium",
+        allow_hyphen_values = true
+    )]
+    rates: Vec<String>,
+
+    /// Roughly how long each clip is
+    #[arg(long, default_value_t = 30.0)]
+    seconds: f64,
+
+    /// Chapter to read from, counted from 1
+    #[arg(long, default_value_t = 1)]
+    chapter: usize,
+
+    /// Read this paragraph of the chapter instead of the first seconds
+    #[arg(long)]
+    paragraph: Option<usize>,
+
+    /// Folder the clips are written to
+    #[arg(long, default_value = "samples")]
+    output: PathBuf,
+
+    /// Speech synthesis backend
+    #[arg(long, value_enum, default_value_t = EngineKind::Edge)]
+    engine: EngineKind,
+}
+
+#[derive(clap::Args, Debug)]
+struct BatchArgs {
+    /// Folder of books (searched recursively), a CSV file with a "file" column and
+    /// optional input_format, opf, cover, output_format, name_template and metadata
+    /// columns, or a text file with one book per line
+    source: PathBuf,
+
+    /// Convert every book, even when its audiobook is newer than the input, and replace
+    /// the audiobook made before
+    #[arg(long)]
+    force: bool,
+
+    /// Where to write the summary [default: <output>/batch-report.txt]
+    #[arg(long)]
+    report: Option<PathBuf>,
+
+    #[command(flatten)]
+    build: BuildArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct WatchArgs {
+    /// Folder to watch for EPUBs and other books; a text book can come with an OPF and
+    /// a cover of the same name
+    inbox: PathBuf,
+
+    /// Where finished audiobooks are moved [default: "outbox" next to the inbox]
+    #[arg(long)]
+    outbox: Option<PathBuf>,
+
+    /// Where converted books are moved [default: "processed" next to the inbox]
+    #[arg(long)]
+    processed: Option<PathBuf>,
+
+    /// Where books that failed are moved, with a log [default: "errors" next to the inbox]
+    #[arg(long)]
+    errors: Option<PathBuf>,
+
+    /// Seconds between looks at the inbox
+    #[arg(long, default_value_t = 10)]
+    interval: u64,
+
+    #[command(flatten)]
+    build: BuildArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct ServeArgs {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    address: String,
+
+    /// Folder the jobs, their uploads and results are kept in
+    #[arg(long, default_value = "jobs")]
+    jobs_dir: PathBuf,
+
+    /// Defaults for the jobs; voice, rate, output format and metadata can be set per job
+    #[command(flatten)]
+    build: BuildArgs,
+}
+
+// Overrides from the metadata file come first so --meta flags win over them
+fn collect_overrides(
+    meta_file: Option<&str>,
+    meta: &[(String, String)],
+) -> Result<Vec<(String, String)>, String> {
+    let mut overrides = match meta_file {
+        Some(path) => metdata::read_meta_file(path)?,
+        None => Vec::new(),
+    };
+    overrides.extend(meta.iter().cloned());
+    Ok(overrides)
+}
+
+fn apply_overrides(metadata: &mut BookMetadata, overrides: &[(String, String)]) {
+    for (key, value) in overrides {
+        if let Err(e) = metadata.apply_override(key, value) {
+            metadata.warnings.push(e);
+        }
+    }
+}
+
+// Settings from edgeab.toml fill in what the command line left out, for the commands
+// that build books
+fn configure(args: &mut Args, matches: &ArgMatches) -> Result<(), String> {
+    let build = match &mut args.command {
+        Command::Build(book_args) | Command::Convert(book_args) => &mut book_args.build,
+        Command::Batch(batch_args) => &mut batch_args.build,
+        Command::Watch(watch_args) => &mut watch_args.build,
+        Command::Serve(serve_args) => &mut serve_args.build,
+        Command::Config(ConfigCommand::Show(show_args)) => &mut show_args.build,
+        Command::Extract(extract_args) => {
+            let config = config::load(extract_args.profile.as_deref())?;
+            let setting = config
+                .get("skip_chapter")
+                .or_else(|| config.get("skip_chapters"));
+            if let Some(setting) = setting.filter(|_| extract_args.skip_chapter.is_empty()) {
+                extract_args.skip_chapter = setting.strings()?;
+            }
+            return Ok(());
+        }
+        _ => return Ok(()),
+    };
+    let config = config::load(build.profile.as_deref())?;
+    build.apply_config(&config, matches)
+}
+
+fn show_config(build: &BuildArgs, matches: &ArgMatches) {
+    // Loaded again for the origins, it was already checked by configure
+    let config = config::load(build.profile.as_deref()).unwrap_or_default();
+    if config.files.is_empty() {
+        let user = config::user_path().map(|path| format!(" or {}", path.display()));
+        say!(
+            "{}",
+            format!("No {}{} found", config::FILE_NAME, user.unwrap_or_default()).yellow()
+        );
+    }
+    for file in &config.files {
+        say!("Config file: {}", file.display());
+    }
+    if let Some(profile) = &config.profile {
+        say!("Profile: {}", profile);
+    }
+    for (key, value) in build.settings() {
+        let origin = if matches.value_source(key) == Some(ValueSource::CommandLine) {
+            "command line".green().to_string()
+        } else if let Some(setting) = config.get(key).or_else(|| match key {
+            "skip_chapter" => config.get("skip_chapters"),
+            "transcript" => config.get("transcripts"),
+            _ => None,
+        }) {
+            setting.origin.display().to_string().cyan().to_string()
+        } else {
+            "default".bright_black().to_string()
+        };
+        say!("{:<15} {:<30} {}", key, value, origin);
+    }
+}
+
+fn tag_book(args: TagArgs) -> Result<(), String> {
+    let overrides = collect_overrides(args.meta_file.as_deref(), &args.meta)?;
+
+    let mut metadata = match &args.opf {
+        Some(opf_file) => {
+            let mut metadata = metdata::get_metadata(opf_file);
+            // The OPF knows nothing of parts, a split file keeps its own
+            metadata.part = metdata::read_tags(&args.file)
+                .ok()
+                .and_then(|tags| tags.part);
+            metadata
+        }
+        None => {
+            metdata::read_tags(&args.file).map_err(|e| format!("Failed to read tags: {}", e))?
+        }
+    };
+    apply_overrides(&mut metadata, &overrides);
+    print_warnings(&metadata.warnings);
+
+    // The prepared cover goes to a folder of its own, not next to the user's files
+    let scratch = scratch_dir("tag");
+    let cover = args
+        .cover
+        .as_ref()
+        .map(|cover_image| cover::prepare_cover(cover_image, &args.cover_settings, &scratch))
+        .transpose();
+    let result = cover.and_then(|cover| metdata::retag_file(&args.file, &metadata, cover.as_ref()));
+    fs::remove_dir_all(&scratch).ok();
+    result?;
+    say!("{}", format!("Updated tags of {}", args.file).green());
+    Ok(())
+}
+
+fn write_feed(args: FeedArgs) -> Result<(), String> {
+    let mut metadata = match &args.opf {
+        Some(opf_file) => metdata::get_metadata(opf_file),
+        None => BookMetadata::default(),
+    };
+    if args.opf.is_none() {
+        // The podcast is named after the book the first file belongs to
+        let files = feed::audio_files(&args.dir).unwrap_or_default();
+        if let Some(tags) = files
+            .iter()
+            .filter_map(|file| metdata::read_tags(&file.to_string_lossy()).ok())
+            .find(|tags| !tags.title.is_empty())
+        {
+            metadata = tags;
+        }
+    }
+    apply_overrides(&mut metadata, &args.meta);
+    if metadata.title.is_empty() {
+        metadata.title = args
+            .dir
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "Audiobooks".to_string());
+    }
+    print_warnings(&metadata.warnings);
+
+    let scratch = scratch_dir("feed");
+    let cover = args
+        .cover
+        .as_ref()
+        .map(|cover_image| cover::prepare_cover(cover_image, &args.cover_settings, &scratch))
+        .transpose();
+    let output = args.output.unwrap_or_else(|| args.dir.join("feed.xml"));
+    let result = cover.and_then(|cover| {
+        feed::write_feed(
+            &args.dir,
+            &args.base_url,
+            &output,
+            &metadata,
+            cover.as_ref(),
+        )
+    });
+    fs::remove_dir_all(&scratch).ok();
+    let episodes = result?;
+    say!(
+        "{}",
+        format!("Wrote {} with {} episodes", output.display(), episodes).green()
+    );
+    Ok(())
+}
+
+async fn write_samples(args: SampleArgs) -> Result<(), String> {
+    // The book is read in a private temp folder, the audio of an unfinished build stays
+    let scratch = scratch_dir("sample");
+    let book = read_any_book(&args.file, args.input_format.as_deref(), &scratch);
+    fs::remove_dir_all(&scratch).ok();
+    let (book, _) = book?;
+    fs::create_dir_all(&args.output)
+        .map_err(|e| format!("Failed to create {}: {}", args.output.display(), e))?;
+
+    // Clips are MP3 so they play anywhere
+    let engine = args.engine.engine();
+    let mut number = 0;
+    let mut failed = 0;
+    for name in &args.voices {
+        for rate in &args.rates {
+            number += 1;
+            let voice = Voice {
+                name: name.trim().to_string(),
+                rate: rate.trim().to_string(),
+            };
+            // The same for every voice, so there's no point going on
+            let text =
+                sample::sample_text(&book, args.chapter, args.paragraph, args.seconds, &voice)?;
+            let clip = sample::clip_path(&args.output, number, &voice, "mp3");
+            let clip_name = clip.to_string_lossy().to_string();
+            match gen_audio(engine.clone(), &voice, text, clip_name, TtsFormat::Mp3).await {
+                Ok(()) => say!("{}", format!("Written {}", clip.display()).green()),
+                Err(e) => {
+                    let message = format!("{} at {} failed: {}", voice.name, voice.rate, e);
+                    say!("{}", message.red());
+                    failed += 1;
+                }
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} samples failed", failed, number));
+    }
+    Ok(())
+}
+
+async fn run_batch(args: BatchArgs) -> Result<(), String> {
+    let items = batch::collect_items(&args.source)?;
+    if items.is_empty() {
+        let message = format!("No books found in {}", args.source.display());
+        say!("{}", message.yellow());
+        return Ok(());
+    }
+    let report = args
+        .report
+        .unwrap_or_else(|| args.build.output.join(batch::REPORT_NAME));
+    let mut options = args.build.build_options()?;
+    // A book made again replaces its old audiobook instead of adding "Title (2)"
+    options.overwrite = true;
+
+    let total = items.len();
+    let mut results = Vec::new();
+    for (i, item) in items.into_iter().enumerate() {
+        let heading = format!("[{}/{}] {}", i + 1, total, item.file.display());
+        say!("{}", heading.green());
+        let started = Instant::now();
+        let outcome = convert_isolated(&item, &options, args.force).await;
+        match &outcome {
+            Outcome::Done(_) => {}
+            Outcome::Skipped(output) => {
+                let message = format!("Up to date: {}", output.display());
+                say!("{}", message.yellow())
+            }
+            Outcome::Failed(e) => say!("{}", format!("Failed: {}", e).red()),
+            Outcome::DryRun => {}
+        }
+        results.push((item, outcome, started.elapsed()));
+    }
+    fs::remove_dir_all(&options.temp_dir).ok();
+
+    if let Some(parent) = report.parent() {
+        fs::create_dir_all(parent).ok();
+    }
+    batch::write_report(&report, &results)
+        .map_err(|e| format!("Failed to write the report: {}", e))?;
+    say!("Report written to {}", report.display());
+
+    let failed = results
+        .iter()
+        .filter(|(_, outcome, _)| matches!(outcome, Outcome::Failed(_)))
+        .count();
+    if failed > 0 {
+        return Err(format!("{} of {} books failed", failed, total));
+    }
+    Ok(())
+}
+
+async fn watch_inbox(args: WatchArgs) -> Result<(), String> {
+    // A dry run would move every book to the processed folder without an audiobook
+    if args.build.dry_run {
+        return Err("watch can't do a dry run, use batch --dry-run".to_string());
+    }
+    // The default folders go next to the inbox, which takes its full path: the parent
+    // of "." is ""
+    let inbox = fs::create_dir_all(&args.inbox)
+        .and_then(|_| fs::canonicalize(&args.inbox))
+        .map_err(|e| format!("Failed to open the inbox {}: {}", args.inbox.display(), e))?;
+    let sibling = |name: &str| {
+        let parent = inbox.parent().unwrap_or(Path::new(""));
+        parent.join(name)
+    };
+    let folders = watch::WatchFolders {
+        outbox: args.outbox.clone().unwrap_or_else(|| sibling("outbox")),
+        processed: args
+            .processed
+            .clone()
+            .unwrap_or_else(|| sibling("processed")),
+        errors: args.errors.clone().unwrap_or_else(|| sibling("errors")),
+        inbox: args.inbox.clone(),
+    };
+    folders
+        .create()
+        .map_err(|e| format!("Failed to create the folders: {}", e))?;
+    let mut options = args.build.build_options()?;
+    // Books are made in the work folder and moved to the outbox when they're done
+    let work_dir = folders.work_dir();
+    options.naming.dir = work_dir.clone();
+
+    let message = format!(
+        "Watching {} for books, finished audiobooks go to {} (Ctrl+C to stop)",
+        folders.inbox.display(),
+        folders.outbox.display()
+    );
+    say!("{}", message.green());
+    let mut settling = watch::Settling::default();
+    loop {
+        let items = match batch::collect_items(&folders.inbox) {
+            Ok(items) => items,
+            Err(e) => {
+                say!("{}", e.red());
+                Vec::new()
+            }
+        };
+        for item in items {
+            let inputs = item.inputs();
+            // Files still being copied in are left for the next round
+            if !settling.is_settled(&inputs) {
+                continue;
+            }
+            say!("{}", format!("Converting {}", item.file.display()).green());
+            fs::remove_dir_all(&work_dir).ok();
+            match convert_isolated(&item, &options, true).await {
+                Outcome::Done(_) | Outcome::Skipped(_) => {
+                    match watch::deliver(&work_dir, &folders.outbox) {
+                        Ok(delivered) => {
+                            for path in delivered {
+                                say!("{}", format!("Delivered {}", path.display()).green());
+                            }
+                        }
+                        Err(e) => {
+                            let message = format!("Failed to move the audiobook: {}", e);
+                            say!("{}", message.red())
+                        }
+                    }
+                    if let Err(e) = watch::archive(&inputs, &folders.processed) {
+                        let message = format!("Failed to move the book out of the inbox: {}", e);
+                        say!("{}", message.red());
+                    }
+                }
+                Outcome::Failed(e) => {
+                    say!("{}", format!("Failed: {}", e).red());
+                    match watch::write_error_log(&folders.errors, &inputs, &e) {
+                        Ok(log) => say!("Log written to {}", log.display()),
+                        Err(e) => say!("{}", format!("Failed to write the log: {}", e).red()),
+                    }
+                    if let Err(e) = watch::archive(&inputs, &folders.errors) {
+                        let message = format!("Failed to move the book out of the inbox: {}", e);
+                        say!("{}", message.red());
+                    }
+                }
+                // Refused before watching starts
+                Outcome::DryRun => {}
+            }
+            fs::remove_dir_all(&work_dir).ok();
+            fs::remove_dir_all(&options.temp_dir).ok();
+            settling.forget(&inputs);
+        }
+        tokio::time::sleep(Duration::from_secs(args.interval)).await;
+    }
+}
+
+async fn serve(args: ServeArgs) -> Result<(), String> {
+    // Jobs would be done without any files
+    if args.build.dry_run {
+        return Err("serve can't do a dry run, use batch --dry-run".to_string());
+    }
+    let options = args.build.build_options()?;
+    let store = server::JobStore::load(&args.jobs_dir).map_err(|e| {
+        format!(
+            "Failed to load the jobs in {}: {}",
+            args.jobs_dir.display(),
+            e
+        )
+    })?;
+    let state = Arc::new(server::ServerState::new(store));
+
+    let http_state = state.clone();
+    let address = args.address.clone();
+    std::thread::spawn(move || {
+        if let Err(e) = server::serve_http(&address, http_state) {
+            eprintln!("{}", e.red());
+            std::process::exit(1);
+        }
+    });
+    say!(
+        "{}",
+        format!("Listening on http://{}", args.address).green()
+    );
+
+    // Jobs share the temp folder, so they run one after the other
+    loop {
+        let next = state
+            .store
+            .lock()
+            .ok()
+            .and_then(|store| store.next_queued());
+        match next {
+            Some(job) => run_job(&state, job, &options).await,
+            None => state.wake.notified().await,
+        }
+    }
+}
+
+async fn run_job(state: &server::ServerState, mut job: server::Job, options: &BuildOptions) {
+    let (input, output_dir) = match state.store.lock() {
+        Ok(store) => (store.input_path(&job), store.output_dir(&job.id)),
+        Err(_) => return,
+    };
+    let mut options = options.clone();
+    options.naming.dir = output_dir.clone();
+    if let Some(voice) = &job.voice {
+        options.voice.name = voice.clone();
+    }
+    if let Some(rate) = &job.rate {
+        options.voice.rate = rate.clone();
+    }
+    let progress = Arc::new(Progress::default());
+    options.progress = progress.clone();
+    let item = BatchItem {
+        file: input,
+        output_format: job.output_format,
+        metadata: job.metadata.clone(),
+        ..BatchItem::default()
+    };
+
+    say!(
+        "{}",
+        format!("Starting job {} ({})", job.id, job.file).green()
+    );
+    job.status = server::JobStatus::Running;
+    save_job(state, &job);
+    if let Ok(mut running) = state.running.lock() {
+        *running = Some((job.id.clone(), progress));
+    }
+
+    match convert_isolated(&item, &options, true).await {
+        Outcome::Done(_) | Outcome::Skipped(_) => {
+            job.status = server::JobStatus::Done;
+            job.outputs = server::list_outputs(&output_dir);
+            say!("{}", format!("Job {} done", job.id).green());
+        }
+        Outcome::Failed(e) => {
+            say!("{}", format!("Job {} failed: {}", job.id, e).red());
+            job.status = server::JobStatus::Failed;
+            job.error = Some(e);
+        }
+        // Refused when the server starts
+        Outcome::DryRun => {}
+    }
+    if let Ok(mut running) = state.running.lock() {
+        *running = None;
+    }
+    save_job(state, &job);
+    fs::remove_dir_all(&options.temp_dir).ok();
+}
+
+fn save_job(state: &server::ServerState, job: &server::Job) {
+    let saved = match state.store.lock() {
+        Ok(mut store) => store.update(job.clone()),
+        Err(_) => return,
+    };
+    if let Err(e) = saved {
+        let message = format!("Failed to save job {}: {}", job.id, e);
+        say!("{}", message.red());
+    }
+}
+
+// A panic deep in the pipeline fails this book, not the whole batch
+async fn convert_isolated(item: &BatchItem, options: &BuildOptions, force: bool) -> Outcome {
+    let (item, options) = (item.clone(), options.clone());
+    task::spawn(async move { convert_item(&item, &options, force).await })
+        .await
+        .unwrap_or_else(|e| Outcome::Failed(format!("Conversion crashed: {}", e)))
+}
+
+// Every book starts with an empty temp folder, so no chapter of the previous one is
+// picked up. EPUBs are converted straight away, without the editable book.txt step.
+async fn convert_item(item: &BatchItem, options: &BuildOptions, force: bool) -> Outcome {
+    if !item.file.is_file() {
+        return Outcome::Failed("File not found".to_string());
+    }
+    fs::remove_dir_all(&options.temp_dir).ok();
+    fs::create_dir_all(&options.temp_dir).ok();
+
+    let mut options = options.clone();
+    if let Some(format) = item.output_format {
+        options.output_format = format;
+    }
+    if let Some(template) = &item.name_template {
+        options.naming.template = template.clone();
+    }
+    options
+        .metadata_overrides
+        .extend(item.metadata.iter().cloned());
+
+    let file = item.file.to_string_lossy().to_string();
+    let path_string =
+        |path: &Option<PathBuf>| path.as_ref().map(|path| path.to_string_lossy().to_string());
+    let mut opf_file = path_string(&item.opf);
+    let mut cover = path_string(&item.cover);
+    // An explicit input format means the file is read as is, EPUB or not
+    let is_epub = item.input_format.is_none() && epub::is_epub(&item.file);
+    // Each EPUB is its own read-along source
+    if options.read_along.is_some() {
+        options.read_along = is_epub.then(|| file.clone());
+    }
+
+    let book = if is_epub {
+        let stem = item.file.file_stem().unwrap_or_default().to_string_lossy();
+        let text_file = format!("{}/{}.txt", options.temp_dir.display(), stem);
+        if let Err(e) = epub::make_file(&file, &text_file, &options.skip_chapters) {
+            return Outcome::Failed(format!("Failed to extract the text: {}", e));
+        }
+        if item.opf.is_none() {
+            let extracted = format!("{}/content.opf", options.temp_dir.display());
+            if epub::extract_opf(&file, &extracted).is_ok() {
+                opf_file = Some(extracted);
+            }
+        }
+        if item.cover.is_none() {
+            if let Ok(Some(extracted)) = epub::extract_cover(&file, &options.temp_dir) {
+                cover = Some(extracted);
+            }
+        }
+        read_book_file(&text_file, Some("txt"))
+    } else {
+        read_book_file(&file, item.input_format.as_deref())
+    };
+    let book = match book {
+        Ok(book) => book,
+        Err(e) => return Outcome::Failed(e),
+    };
+
+    if !force {
+        let metadata = book_metadata(
+            opf_file.as_deref(),
+            book.get_title(),
+            &options.metadata_overrides,
+        );
+        let planned = planned_outputs(&metadata, &options);
+        if let Some(output) = batch::up_to_date(&item.inputs(), &planned) {
+            return Outcome::Skipped(output);
+        }
+    }
+    let result = make_book(book, opf_file.as_deref(), cover.as_deref(), &options).await;
+    options.progress.finished(&result);
+    match result {
+        Ok(_) if options.dry_run => Outcome::DryRun,
+        Ok(outputs) => Outcome::Done(outputs),
+        Err(e) => Outcome::Failed(e),
+    }
+}
+
+// Where make_book puts the book (or its first part), to tell whether it was made already
+fn planned_outputs(metadata: &BookMetadata, options: &BuildOptions) -> Vec<PathBuf> {
+    let naming = book_naming(options);
+    let path = naming.path_for(metadata);
+    if options.output_format == OutputFormat::Chapters {
+        return vec![path];
+    }
+    let mut first_part = metadata.clone();
+    first_part.part = Some((1, 2));
+    [path, naming.path_for(&first_part)]
+        .into_iter()
+        .map(|path| {
+            let mut name = path.into_os_string();
+            name.push(".");
+            name.push(options.output_format.file_extension());
+            PathBuf::from(name)
+        })
+        .collect()
 }
 
 #[tokio::main]
 async fn main() {
-    fs::create_dir_all(AUDIO_OUTPUT_DIR).ok();
-    let args = Args::parse();
-    
-
-    let file_path = args.file;
-    let opf_file = args.opf.unwrap_or_else(|| "none.opf".to_string()); // Use a default or handle None case
-    let cover = args.cover.unwrap_or_else(|| "none.img".to_string());
-    println!("file: {}, opf: {}, cover: {}", file_path,opf_file, cover);
-
-    if file_path.ends_with(".txt") {
-        if opf_file != "none.opf" {
-            if cover == "none.img" {
-                println!("{}", "no cover image provided".yellow())
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    // The build options are in the innermost subcommand
+    let mut matches = &matches;
+    while let Some((_, sub_matches)) = matches.subcommand() {
+        matches = sub_matches;
+    }
+    let result = match configure(&mut args, matches) {
+        Ok(()) => run_command(args.command, matches).await,
+        Err(e) => Err(e),
+    };
+    // Scripts go by the exit code
+    if let Err(e) = result {
+        eprintln!("{}", e.red());
+        std::process::exit(1);
+    }
+}
+
+async fn run_command(command: Command, matches: &ArgMatches) -> Result<(), String> {
+    match command {
+        Command::Extract(extract_args) => extract_book(extract_args),
+        Command::Build(book_args) => build_command(book_args).await,
+        Command::Convert(book_args) => convert_command(book_args).await,
+        Command::Inspect(inspect_args) => inspect(inspect_args),
+        Command::Validate(validate_args) => validate(validate_args),
+        Command::Tag(tag_args) => tag_book(tag_args),
+        Command::Batch(batch_args) => run_batch(batch_args).await,
+        Command::Watch(watch_args) => watch_inbox(watch_args).await,
+        Command::Serve(serve_args) => serve(serve_args).await,
+        Command::Feed(feed_args) => write_feed(feed_args),
+        Command::Sample(sample_args) => write_samples(sample_args).await,
+        Command::Config(ConfigCommand::Show(show_args)) => {
+            show_config(&show_args.build, matches);
+            Ok(())
+        }
+    }
+}
+
+fn extract_book(args: ExtractArgs) -> Result<(), String> {
+    if !epub::is_epub(Path::new(&args.file)) {
+        return Err(format!(
+            "{} isn't an EPUB; extract only reads EPUBs, build takes {} files as they are",
+            args.file,
+            input::format_names().join(", ")
+        ));
+    }
+    if !Path::new(&args.file).is_file() {
+        return Err(format!("{} doesn't exist", args.file));
+    }
+    let dir = args.output.parent().unwrap_or(Path::new(""));
+    if !dir.as_os_str().is_empty() {
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+
+    let output = args.output.to_string_lossy().to_string();
+    epub::make_file(&args.file, &output, &args.skip_chapter)
+        .map_err(|e| format!("Failed to extract the text: {}", e))?;
+    say!(
+        "{}",
+        format!("Text written to {}, you can edit it now", output).green()
+    );
+    let mut build = format!("edgeab-rs build {}", output);
+    let mut failed = false;
+
+    let opf_file = dir.join("content.opf").to_string_lossy().to_string();
+    match epub::extract_opf(&args.file, &opf_file) {
+        Ok(()) => {
+            say!("{}", format!("Metadata written to {}", opf_file).green());
+            build.push_str(&format!(" --opf {}", opf_file));
+        }
+        Err(e) => {
+            say!("{}", format!("Failed to extract the OPF: {}", e).red());
+            failed = true;
+        }
+    }
+    match epub::extract_cover(&args.file, dir) {
+        Ok(Some(cover_path)) => {
+            say!("{}", format!("Cover written to {}", cover_path).green());
+            build.push_str(&format!(" --cover {}", cover_path));
+        }
+        Ok(None) => say!("{}", "The EPUB has no cover image".yellow()),
+        Err(e) => {
+            say!("{}", format!("Failed to extract the cover: {}", e).red());
+            failed = true;
+        }
+    }
+    say!("Then make the audiobook with: {}", build);
+    if failed {
+        return Err(format!("{} was only partly extracted", args.file));
+    }
+    Ok(())
+}
+
+async fn build_command(args: BookArgs) -> Result<(), String> {
+    if args.input_format.is_none() && epub::is_epub(Path::new(&args.file)) {
+        return Err(format!(
+            "{} is an EPUB: run `edgeab-rs extract {}` to get an editable book.txt, or \
+             `edgeab-rs convert {}` to make the audiobook straight away",
+            args.file, args.file, args.file
+        ));
+    }
+    if !Path::new(&args.file).is_file() {
+        return Err(format!("{} doesn't exist", args.file));
+    }
+    if input::find_format(&args.file, args.input_format.as_deref()).is_none() {
+        return Err(format!(
+            "Can't build from {}: expected one of: {} (or pass --input-format)",
+            args.file,
+            input::format_names().join(", ")
+        ));
+    }
+    let options = args.build.build_options()?;
+    if args.opf.is_none() {
+        say!("{}", "No OPF file given".yellow())
+    }
+    if args.cover.is_none() {
+        say!(
+            "{}",
+            "No cover image given, using the OPF cover or a generated one".yellow()
+        )
+    }
+
+    fs::create_dir_all(&options.temp_dir).ok();
+    let result = build_book(
+        &args.file,
+        args.input_format.as_deref(),
+        args.opf.as_deref(),
+        args.cover.as_deref(),
+        &options,
+    )
+    .await;
+    fs::remove_dir_all(&options.temp_dir).ok();
+    for output in result? {
+        say!("{}", format!("Written {}", output.display()).green());
+    }
+    Ok(())
+}
+
+// Goes through the same steps as a book of a batch, EPUBs are extracted on the way
+async fn convert_command(args: BookArgs) -> Result<(), String> {
+    let file = PathBuf::from(&args.file);
+    if !file.is_file() {
+        return Err(format!("{} doesn't exist", args.file));
+    }
+    let supported = epub::is_epub(&file)
+        || input::find_format(&args.file, args.input_format.as_deref()).is_some();
+    if !supported {
+        return Err(format!(
+            "Can't convert {}: expected an EPUB or one of: {} (or pass --input-format)",
+            args.file,
+            input::format_names().join(", ")
+        ));
+    }
+    let options = args.build.build_options()?;
+    let item = BatchItem {
+        file,
+        input_format: args.input_format,
+        opf: args.opf.map(PathBuf::from),
+        cover: args.cover.map(PathBuf::from),
+        ..BatchItem::default()
+    };
+    let outcome = convert_isolated(&item, &options, true).await;
+    fs::remove_dir_all(&options.temp_dir).ok();
+    match outcome {
+        Outcome::Done(outputs) => {
+            for output in outputs {
+                say!("{}", format!("Written {}", output.display()).green());
             }
-            make_book(&file_path, &opf_file, &cover).await;
-        } else {
-            let message = "Missing OPF file";
-            println!("{}", message.red())
+            Ok(())
+        }
+        Outcome::Skipped(_) | Outcome::DryRun => Ok(()),
+        Outcome::Failed(e) => Err(e),
+    }
+}
+
+// Reads a book of any supported format. EPUBs are extracted to `scratch` first, and
+// come with their OPF.
+fn read_any_book(
+    file: &str,
+    input_format: Option<&str>,
+    scratch: &Path,
+) -> Result<(Book, Option<String>), String> {
+    if !Path::new(file).is_file() {
+        return Err(format!("{} doesn't exist", file));
+    }
+    if input_format.is_some() || !epub::is_epub(Path::new(file)) {
+        return Ok((read_book_file(file, input_format)?, None));
+    }
+    fs::create_dir_all(scratch)
+        .map_err(|e| format!("Failed to create {}: {}", scratch.display(), e))?;
+    let stem = Path::new(file)
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let text_file = scratch.join(format!("{}.txt", stem));
+    let text_file = text_file.to_string_lossy();
+    epub::make_file(file, &text_file, &[])
+        .map_err(|e| format!("Failed to extract the text: {}", e))?;
+    let opf_file = scratch.join("content.opf").to_string_lossy().to_string();
+    let opf_file = epub::extract_opf(file, &opf_file).ok().map(|_| opf_file);
+    Ok((read_book_file(&text_file, Some("txt"))?, opf_file))
+}
+
+// A folder of its own in the system temp folder for a command that doesn't build a
+// book, the build's temp folder holds the audio of an unfinished one
+fn scratch_dir(command: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("edgeab-{}-{}", command, std::process::id()))
+}
+
+fn print_metadata(metadata: &BookMetadata) {
+    let fields = [
+        ("Title", Some(metadata.title.clone())),
+        ("Subtitle", metadata.subtitle.clone()),
+        ("Author", Some(metadata.authors.join(", "))),
+        ("Narrator", Some(metadata.narrators.join(", "))),
+        ("Series", metadata.series.clone()),
+        ("Series index", metadata.series_index.clone()),
+        ("Language", metadata.language.clone()),
+        ("Publisher", metadata.publisher.clone()),
+        ("Date", metadata.date.clone()),
+    ];
+    for (name, value) in fields {
+        if let Some(value) = value.filter(|value| !value.is_empty()) {
+            say!("{:<13} {}", format!("{}:", name), value);
+        }
+    }
+}
+
+fn inspect(args: InspectArgs) -> Result<(), String> {
+    let path = Path::new(&args.file);
+    if args.input_format.is_none() && feed::is_audio(path) {
+        if !path.is_file() {
+            return Err(format!("{} doesn't exist", args.file));
+        }
+        print_metadata(&metdata::read_tags(&args.file)?);
+        if let Ok(length) = ffmpeg::get_audio_length(&args.file) {
+            let duration = estimate::format_duration(length / 1000.0);
+            say!("{:<13} {}", "Duration:", duration);
+        }
+        let chapters = ffmpeg::get_chapters(&args.file)?;
+        say!("{} chapters", chapters.len());
+        for (number, (title, start, _)) in chapters.iter().enumerate() {
+            let start = estimate::format_duration(start / 1000.0);
+            say!("{:>4}  {}  {}", number + 1, start, title);
+        }
+        return Ok(());
+    }
+
+    let scratch = scratch_dir("inspect");
+    let book = read_any_book(&args.file, args.input_format.as_deref(), &scratch);
+    let book = book.map(|(book, opf_file)| {
+        let metadata = book_metadata(opf_file.as_deref(), book.get_title(), &[]);
+        (book, metadata)
+    });
+    fs::remove_dir_all(&scratch).ok();
+    let (book, metadata) = book?;
+    print_metadata(&metadata);
+    let chapters = book.get_all_chapters();
+    let paragraphs: usize = chapters.iter().map(|(_, content)| content.len()).sum();
+    say!("{} chapters, {} paragraphs", chapters.len(), paragraphs);
+    for (number, (title, content)) in chapters.iter().enumerate() {
+        let characters: usize = content.iter().map(|text| text.chars().count()).sum();
+        say!(
+            "{:>4}  {} ({} paragraphs, {} characters)",
+            number + 1,
+            title,
+            content.len(),
+            characters
+        );
+    }
+    Ok(())
+}
+
+// Problems stop the conversion (or leave chapters out), warnings only make a poorer
+// audiobook. Exits with 1 when there are problems, for scripts.
+fn validate(args: ValidateArgs) -> Result<(), String> {
+    let mut problems = Vec::new();
+    let mut warnings = Vec::new();
+
+    let scratch = scratch_dir("validate");
+    match read_any_book(&args.file, args.input_format.as_deref(), &scratch) {
+        Ok((book, epub_opf)) => {
+            let chapters = book.get_all_chapters();
+            if chapters.is_empty() {
+                problems.push("The book has no chapters (\"# \" headings in text)".to_string());
+            }
+            for (number, (title, content)) in chapters.iter().enumerate() {
+                if content.len() < 2 {
+                    problems.push(format!(
+                        "Chapter {} ({}) has fewer than 2 paragraphs and would be left out",
+                        number + 1,
+                        title
+                    ));
+                }
+            }
+            let opf_file = args.opf.clone().or(epub_opf);
+            if let Some(opf_file) = opf_file.as_deref().filter(|opf| !Path::new(opf).is_file()) {
+                problems.push(format!("The OPF file {} doesn't exist", opf_file));
+            } else {
+                let metadata = book_metadata(opf_file.as_deref(), book.get_title(), &[]);
+                warnings.extend(metadata.warnings);
+            }
+        }
+        Err(e) => problems.push(e),
+    }
+    fs::remove_dir_all(&scratch).ok();
+
+    if let Some(cover) = &args.cover {
+        if let Err(e) = image::image_dimensions(cover) {
+            problems.push(format!("The cover image {} can't be read: {}", cover, e));
         }
-        // If opf is None, you can provide some default logic for handling
-    } else if file_path.ends_with(".epub") {
-        println!(
+    }
+
+    for warning in &warnings {
+        say!(
             "{}",
-            "Creating Intermediate File You can edit this".yellow()
+            format!("Warning: {}", warning.trim_start_matches("Warning: ")).yellow()
         );
-        epub::make_file(&file_path, "book.txt").ok();
     }
-    fs::remove_dir_all(AUDIO_OUTPUT_DIR).ok();
+    for problem in &problems {
+        say!("{}", format!("Problem: {}", problem).red());
+    }
+    if !problems.is_empty() {
+        return Err(format!("{} isn't ready to convert", args.file));
+    }
+    say!("{}", format!("{} is ready to convert", args.file).green());
+    Ok(())
 }
+
 // Call the read_book function with the book_path
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A job through the worker with the stub engine, which makes its silence with FFmpeg
+    #[tokio::test]
+    async fn stub_engine_job_is_converted() {
+        if std::process::Command::new("ffmpeg")
+            .arg("-version")
+            .output()
+            .is_err()
+        {
+            println!("FFmpeg not found, skipped");
+            return;
+        }
+        let dir = std::env::temp_dir().join(format!("edgeab-worker-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+
+        let args = Args::try_parse_from([
+            "edgeab-rs",
+            "serve",
+            "--engine",
+            "stub",
+            "--cover-template",
+            "none",
+        ])
+        .unwrap();
+        let Command::Serve(serve_args) = args.command else {
+            unreachable!()
+        };
+        let mut options = serve_args.build.build_options().unwrap();
+        options.temp_dir = dir.join("tmp");
+        let mut store = server::JobStore::load(&dir.join("jobs")).unwrap();
+        let job = server::Job {
+            id: "1".to_string(),
+            status: server::JobStatus::Queued,
+            file: "book.txt".to_string(),
+            voice: None,
+            rate: None,
+            output_format: Some(OutputFormat::Mp3),
+            metadata: vec![("title".to_string(), "Stub".to_string())],
+            error: None,
+            outputs: Vec::new(),
+            created: 0,
+        };
+        store.update(job.clone()).unwrap();
+        let book = "# Chapter One\nFirst paragraph.\nSecond paragraph.\n";
+        fs::write(store.input_path(&job), book).unwrap();
+        let state = server::ServerState::new(store);
+
+        run_job(&state, job, &options).await;
+        let saved = fs::read_to_string(dir.join("jobs").join("1").join("job.json")).unwrap();
+        let saved: serde_json::Value = serde_json::from_str(&saved).unwrap();
+        assert_eq!(saved["status"], "done", "{}", saved["error"]);
+        assert_eq!(saved["outputs"][0], "Stub.mp3");
+        assert!(dir.join("jobs/1/out/Stub.mp3").is_file());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -1,11 +1,14 @@
-use image::GenericImageView;
-
-use mp4ameta::{Img, ImgFmt, Tag};
-use regex::Regex;
+use crate::cover::{self, Cover};
+use crate::naming;
+use crate::output::OutputFormat;
+use crate::progress::say;
+use mp4ameta::{Data, FreeformIdent, Tag};
+use scraper::{ElementRef, Html, Node};
+use std::fs;
 use std::fs::File;
 use std::io::BufReader;
+use std::path::Path;
 use std::process::Command;
-use std::{collections::HashMap, fs};
 use xmltree::{Element, XMLNode};
 
 // Helper function to extract the text from an element
@@ -23,33 +26,78 @@ pub fn get_text_from_element(element: &Element) -> String {
         .collect::<Vec<_>>()
         .join("")
 }
-fn square_cover(img_file: &str) {
-    // Load the image from file (replace "input.png" with your file path)
-    let mut img = image::open(img_file).expect("Failed to open image");
-
-    // Get the dimensions of the original image
-    let (width, height) = img.dimensions();
-
-    // Calculate the square side length (based on the width or height, whichever is smaller)
-    let square_size = std::cmp::min(width, height);
+/// Converts HTML (as found in OPF descriptions) to plain text: entities are decoded,
+/// block elements and <br> become paragraph breaks and other whitespace is collapsed.
+pub fn html_to_text(input: &str) -> String {
+    fn walk(element: ElementRef, paragraphs: &mut Vec<String>) {
+        for child in element.children() {
+            match child.value() {
+                Node::Text(text) => {
+                    if let Some(current) = paragraphs.last_mut() {
+                        current.push_str(text);
+                    }
+                }
+                Node::Element(child_element) => {
+                    let is_block = matches!(
+                        child_element.name(),
+                        "p" | "div"
+                            | "br"
+                            | "li"
+                            | "blockquote"
+                            | "h1"
+                            | "h2"
+                            | "h3"
+                            | "h4"
+                            | "h5"
+                            | "h6"
+                            | "tr"
+                            | "ul"
+                            | "ol"
+                    );
+                    if is_block {
+                        paragraphs.push(String::new());
+                    }
+                    if let Some(child_ref) = ElementRef::wrap(child) {
+                        walk(child_ref, paragraphs);
+                    }
+                    if is_block {
+                        paragraphs.push(String::new());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
 
-    // Crop from the top-left corner (0, 0) to the square size
-    let cropped_img = img.crop(0, 0, square_size, square_size);
+    let fragment = Html::parse_fragment(input);
+    let mut paragraphs = vec![String::new()];
+    walk(fragment.root_element(), &mut paragraphs);
 
-    // Save the cropped image (replace "output.png" with your desired output file path)
-    cropped_img
-        .save("bcover.png")
-        .expect("Failed to save image");
+    paragraphs
+        .iter()
+        .map(|paragraph| paragraph.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
 }
 
-fn remove_html_tags(input: &str) -> String {
-    // Define a regular expression to match HTML tags
-    let re = Regex::new(r"<[^>]*>").unwrap();
-
-    // Replace all HTML tags with an empty string
-    let result = re.replace_all(input, "");
+// The MP4 desc atom is meant for a short summary, players cut it at 255 characters
+const SHORT_DESCRIPTION_LENGTH: usize = 255;
 
-    result.to_string()
+/// Shortens text to at most `max_chars` characters, cutting at a word boundary
+fn truncate_text(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let cut: String = text.chars().take(max_chars - 1).collect();
+    let cut = match cut.rfind(char::is_whitespace) {
+        Some(pos) if pos > 0 => &cut[..pos],
+        _ => cut.as_str(),
+    };
+    format!(
+        "{}…",
+        cut.trim_end_matches(|c: char| c.is_whitespace() || c == ',')
+    )
 }
 
 fn _shorten_name(original: &str) -> String {
@@ -65,52 +113,43 @@ fn _shorten_name(original: &str) -> String {
         })
         .collect::<String>() // Collect the characters into a single string
 }
-fn add_cover_to_m4b(m4b_path: &str, cover_image_path: &str) {
-    square_cover(cover_image_path);
-    let cover_path = "bcover.png";
-    // Read the existing tag from the M4B file
-    let mut tag = Tag::read_from_path(m4b_path).expect("Failed to read tag from M4B file");
-
-    // Read the cover image file into a Vec<u8>
-    let image_data = fs::read(cover_path).expect("Failed to read cover image file");
-    let img_format = ImgFmt::Jpeg; // Set the image format as JPEG
-
-    // Create an Img instance from the image data
-    // The Img::new function requires the image data (Vec<u8>) and the format
-    let cover = Img::new(img_format, image_data);
-
-    // Set the artwork in the tag
-    tag.set_artwork(cover);
-
-    // Write the updated tag back to the M4B file
-    tag.write_to_path(m4b_path)
-        .expect("Failed to write updated tag to M4B file");
-}
-
-pub fn add_metadata(input_file: &String, metadata: &HashMap<String, String>, cover_image: &str) {
-    let title = match metadata.get("title") {
-        Some(title) => remove_html_tags(title),
-        None => {
-            eprintln!("Title metadata is required to name the output file.");
-            return;
-        }
-    };
-
-    let output_file = format!("{}.m4b", title);
+/// Remuxes the finished book to `output_file` with the metadata and cover
+pub fn add_metadata(
+    input_file: &String,
+    output_file: &str,
+    metadata: &BookMetadata,
+    cover: Option<&Cover>,
+    format: OutputFormat,
+) {
+    let picture_file = "picture.txt";
     let mut args = vec!["-i", input_file];
+    let mut cover_stream = false;
+    let mut picture_metadata = false;
 
-    let mut metadata_args = Vec::new();
-
-    for (key, value) in metadata {
-        let data = format!("{}={}", key, remove_html_tags(value));
-        metadata_args.push(data);
-
-        if key == "title" {
-            let album_data = format!("album={}", remove_html_tags(value));
-            metadata_args.push(album_data);
+    // MP3 and Opus get their cover while remuxing, M4B gets it through mp4ameta afterwards
+    match (format, cover) {
+        (OutputFormat::Mp3, Some(cover)) => {
+            args.extend(["-i", &cover.path]);
+            cover_stream = true;
         }
+        (OutputFormat::Opus, Some(cover)) => {
+            match cover::write_picture_metadata(cover, picture_file) {
+                Ok(()) => {
+                    args.extend(["-i", picture_file]);
+                    picture_metadata = true;
+                }
+                Err(e) => eprintln!("Failed to embed cover in Opus file: {}", e),
+            }
+        }
+        _ => {}
     }
 
+    let metadata_args: Vec<String> = metadata
+        .tags()
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect();
+
     for data in &metadata_args {
         args.push("-metadata");
         args.push(&data);
@@ -120,10 +159,28 @@ pub fn add_metadata(input_file: &String, metadata: &HashMap<String, String>, cov
     args.push("-map");
     args.push("0:a"); // Only map the audio stream
 
+    if cover_stream {
+        // ID3v2 APIC frame from the attached picture stream
+        args.extend([
+            "-map",
+            "1:v",
+            "-disposition:v",
+            "attached_pic",
+            "-metadata:s:v",
+            "comment=Cover (front)",
+        ]);
+    } else if picture_metadata {
+        args.extend(["-map_metadata", "1"]);
+    }
+    if format == OutputFormat::Mp3 {
+        // v2.3 is the version most players understand CHAP/CTOC frames in
+        args.extend(["-id3v2_version", "3"]);
+    }
+
     // Codec and output file
     args.push("-c");
     args.push("copy");
-    args.push(&output_file);
+    args.push(output_file);
 
     let output = Command::new("ffmpeg")
         .args(&args)
@@ -131,75 +188,998 @@ pub fn add_metadata(input_file: &String, metadata: &HashMap<String, String>, cov
         .expect("Failed to execute FFmpeg");
 
     if output.status.success() {
-        println!("Metadata added successfully to {}", output_file);
+        say!("Metadata added successfully to {}", output_file);
         fs::remove_file(input_file).ok(); // Optionally remove the original file
+        if format == OutputFormat::M4b {
+            if let Err(e) = add_freeform_tags(output_file, metadata) {
+                eprintln!("{}", e);
+            }
+        }
     } else {
         let stderr = String::from_utf8_lossy(&output.stderr);
         eprintln!("FFmpeg error: {}", stderr);
     }
-    if cover_image != "none.img" {
-        add_cover_to_m4b(&output_file, cover_image);
+    fs::remove_file(picture_file).ok();
+
+    match cover {
+        None => say!("no cover img provided"),
+        Some(cover) if format == OutputFormat::M4b => {
+            if let Err(e) = cover::add_cover_to_mp4(output_file, cover) {
+                eprintln!("{}", e);
+            }
+        }
+        Some(_) => {}
+    }
+}
+
+// Tags FFmpeg's MP4 muxer has no atom for and silently drops, with the name of the
+// iTunes freeform atom (----:com.apple.iTunes:NAME) other taggers use for them
+const FREEFORM_TAGS: [(&str, &str); 7] = [
+    ("subtitle", "SUBTITLE"),
+    ("language", "LANGUAGE"),
+    ("publisher", "PUBLISHER"),
+    ("isbn", "ISBN"),
+    ("translator", "TRANSLATOR"),
+    ("illustrator", "ILLUSTRATOR"),
+    ("editor", "EDITOR"),
+];
+
+/// Writes the tags FFmpeg leaves out of MP4 files as freeform atoms. The ones the
+/// metadata doesn't have are removed, so retagging clears them.
+pub fn add_freeform_tags(mp4_path: &str, metadata: &BookMetadata) -> Result<(), String> {
+    let mut tag = Tag::read_from_path(mp4_path)
+        .map_err(|e| format!("Failed to read tags from {}: {}", mp4_path, e))?;
+    let tags = metadata.tags();
+    for (key, name) in FREEFORM_TAGS {
+        let ident = FreeformIdent::new("com.apple.iTunes", name);
+        tag.remove_data_of(&ident);
+        if let Some((_, value)) = tags.iter().find(|(tag, _)| tag == key) {
+            tag.set_data(ident, Data::Utf8(value.clone()));
+        }
+    }
+    tag.write_to_path(mp4_path)
+        .map_err(|e| format!("Failed to write tags to {}: {}", mp4_path, e))
+}
+
+/// "01 - Title.m4a" for the chapter at `index`
+pub fn chapter_file_name(index: usize, title: &str) -> String {
+    format!("{:02} - {}.m4a", index + 1, naming::sanitize(title))
+}
+
+/// Writes each chapter to its own file in `output_dir`, tagged with the book metadata,
+/// the chapter title and its track number. `chapters` has the index in the book and the
+/// title of each file, the file is numbered after the book chapter.
+pub fn tag_chapter_files(
+    chapter_files: &[String],
+    chapters: &[(usize, String)],
+    output_dir: &Path,
+    metadata: &BookMetadata,
+    cover: Option<&Cover>,
+) {
+    let book_title = html_to_text(&metadata.title);
+    if let Err(e) = fs::create_dir_all(output_dir) {
+        eprintln!("Failed to create folder {}: {}", output_dir.display(), e);
+        return;
+    }
+
+    let total = chapter_files.len();
+    for (i, (chapter_file, (index, chapter_title))) in
+        chapter_files.iter().zip(chapters).enumerate()
+    {
+        let output_file = output_dir
+            .join(chapter_file_name(*index, chapter_title))
+            .to_string_lossy()
+            .to_string();
+
+        let mut metadata_args = vec![
+            format!("title={}", chapter_title),
+            format!("album={}", book_title),
+            format!("track={}/{}", i + 1, total),
+        ];
+        for (key, value) in metadata.tags() {
+            if key != "title" && key != "album" {
+                metadata_args.push(format!("{}={}", key, value));
+            }
+        }
+
+        let mut args = vec!["-i", chapter_file.as_str(), "-map", "0:a"];
+        for data in &metadata_args {
+            args.push("-metadata");
+            args.push(data);
+        }
+        args.extend(["-c", "copy", output_file.as_str()]);
+
+        let output = Command::new("ffmpeg")
+            .args(&args)
+            .output()
+            .expect("Failed to execute FFmpeg");
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            eprintln!("FFmpeg error: {}", stderr);
+            continue;
+        }
+        if let Err(e) = add_freeform_tags(&output_file, metadata) {
+            eprintln!("{}", e);
+        }
+        if let Some(cover) = cover {
+            if let Err(e) = cover::add_cover_to_mp4(&output_file, cover) {
+                eprintln!("{}", e);
+            }
+        }
+    }
+    say!("Chapter files written to {}", output_dir.display());
+}
+
+/// Parses a `--meta key=value` argument
+pub fn parse_meta_arg(arg: &str) -> Result<(String, String), String> {
+    match arg.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.to_string()))
+        }
+        _ => Err(format!("Expected key=value, got '{}'", arg)),
+    }
+}
+
+/// Reads metadata overrides from a flat TOML or JSON table. Arrays are turned into
+/// ';' separated lists so they go through the same path as `--meta`.
+pub fn read_meta_file(path: &str) -> Result<Vec<(String, String)>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+    let parse_error = |e: String| format!("Unable to parse {}: {}", path, e);
+
+    if path.ends_with(".json") {
+        let table: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(&text).map_err(|e| parse_error(e.to_string()))?;
+        let to_string = |value: &serde_json::Value| match value {
+            serde_json::Value::String(text) => text.clone(),
+            serde_json::Value::Null => String::new(),
+            other => other.to_string(),
+        };
+
+        Ok(table
+            .iter()
+            .map(|(key, value)| match value {
+                serde_json::Value::Array(items) => (
+                    key.clone(),
+                    items.iter().map(to_string).collect::<Vec<_>>().join(";"),
+                ),
+                other => (key.clone(), to_string(other)),
+            })
+            .collect())
     } else {
-        println!("no cover img provided");
+        let table: toml::Table = toml::from_str(&text).map_err(|e| parse_error(e.to_string()))?;
+        // Dates and numbers are written back without TOML quoting
+        let to_string = |value: &toml::Value| match value {
+            toml::Value::String(text) => text.clone(),
+            other => other.to_string(),
+        };
+
+        Ok(table
+            .iter()
+            .map(|(key, value)| match value {
+                toml::Value::Array(items) => (
+                    key.clone(),
+                    items.iter().map(to_string).collect::<Vec<_>>().join(";"),
+                ),
+                other => (key.clone(), to_string(other)),
+            })
+            .collect())
+    }
+}
+
+// Every tag `BookMetadata::tags` can write, so retagging can clear the ones that went away
+const TAG_KEYS: &[&str] = &[
+    "title",
+    "album",
+    "date",
+    "description",
+    "synopsis",
+    "language",
+    "artist",
+    "album_artist",
+    "composer",
+    "genre",
+    "publisher",
+    "grouping",
+    "isbn",
+    "translator",
+    "illustrator",
+    "editor",
+    "subtitle",
+    "disc",
+];
+
+/// Reads the tags of an already produced audiobook back into `BookMetadata`
+pub fn read_tags(file_path: &str) -> Result<BookMetadata, String> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error", "-show_entries", "format_tags", "-of", "json"])
+        .arg(file_path)
+        .output()
+        .map_err(|e| format!("Failed to execute ffprobe: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("ffprobe failed with status: {}", output.status));
+    }
+
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| format!("Failed to parse ffprobe output: {}", e))?;
+    let mut metadata = BookMetadata::default();
+    let Some(tags) = json["format"]["tags"].as_object() else {
+        return Ok(metadata);
+    };
+
+    for (key, value) in tags {
+        let Some(value) = value.as_str() else {
+            continue;
+        };
+        // Lists were joined with ", " when they were written
+        let list = value.split(", ").collect::<Vec<_>>().join(";");
+        let result = match key.to_lowercase().as_str() {
+            "title" | "date" | "description" | "language" | "publisher" | "isbn" | "subtitle" => {
+                metadata.apply_override(key, value)
+            }
+            "artist" => metadata.apply_override("authors", &list),
+            "composer" => metadata.apply_override("narrators", &list),
+            "genre" => metadata.apply_override("subjects", &list),
+            "translator" | "illustrator" | "editor" => metadata.apply_override(key, &list),
+            "grouping" => match value.rsplit_once(" #") {
+                Some((series, index)) => metadata
+                    .apply_override("series", series)
+                    .and_then(|_| metadata.apply_override("series_index", index)),
+                None => metadata.apply_override("series", value),
+            },
+            // "2/3", or just "2" from other taggers
+            "disc" => {
+                let (part, parts) = value.split_once('/').unwrap_or((value, value));
+                match (part.trim().parse(), parts.trim().parse()) {
+                    (Ok(part), Ok(parts)) if part > 0 && part <= parts => {
+                        metadata.part = Some((part, parts));
+                        Ok(())
+                    }
+                    _ => Err(format!("Invalid disc number '{}'", value)),
+                }
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            metadata.warnings.push(e);
+        }
+    }
+    // desc is cut short, the ldes atom (synopsis) keeps the whole description
+    if let Some(synopsis) = tags
+        .get("synopsis")
+        .and_then(|synopsis| synopsis.as_str())
+        .filter(|synopsis| !synopsis.trim().is_empty())
+    {
+        metadata.description = Some(synopsis.to_string());
+    }
+    // The title tag of a split part includes the part, the album holds the book title.
+    // With the disc read back into `part`, full_title() adds the part again.
+    if let Some(album) = tags.get("album").and_then(|album| album.as_str()) {
+        metadata.title = album.to_string();
+    }
+    Ok(metadata)
+}
+
+/// Rewrites the tags (and optionally the cover) of an existing file in place,
+/// copying the audio and chapters as they are
+pub fn retag_file(
+    file_path: &str,
+    metadata: &BookMetadata,
+    cover: Option<&Cover>,
+) -> Result<(), String> {
+    let path = Path::new(file_path);
+    let extension = path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let temp_file = path.with_extension(format!("retag.{}", extension));
+
+    let tags = metadata.tags();
+    let mut metadata_args = Vec::new();
+    for key in TAG_KEYS {
+        // An empty value removes the tag
+        let value = tags
+            .iter()
+            .find(|(tag, _)| tag == key)
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default();
+        metadata_args.push(format!("{}={}", key, value));
+    }
+
+    let mut args = vec!["-y", "-i", file_path, "-map", "0", "-c", "copy"];
+    for data in &metadata_args {
+        args.push("-metadata");
+        args.push(data);
+    }
+    let temp_path = temp_file.to_string_lossy().to_string();
+    args.push(&temp_path);
+
+    let output = Command::new("ffmpeg")
+        .args(&args)
+        .output()
+        .map_err(|e| format!("Failed to execute FFmpeg: {}", e))?;
+    if !output.status.success() {
+        fs::remove_file(&temp_file).ok();
+        return Err(format!(
+            "FFmpeg error: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    fs::rename(&temp_file, path).map_err(|e| format!("Failed to replace {}: {}", file_path, e))?;
+
+    let is_mp4 = ["m4b", "m4a", "mp4"].contains(&extension.to_lowercase().as_str());
+    if is_mp4 {
+        add_freeform_tags(file_path, metadata)?;
+    }
+    match cover {
+        Some(cover) => cover::add_cover_to_mp4(file_path, cover),
+        None => Ok(()),
     }
 }
 
-pub fn get_metadata(file_path: &str) -> HashMap<String, String> {
-    let mut metadata_map = HashMap::new();
+/// Writes the files Audiobookshelf and Plex read next to the audio: metadata.json,
+/// metadata.abs, desc.txt, reader.txt and the cover. Chapters are (title, start ms, end ms).
+pub fn write_sidecars(
+    dir: &Path,
+    metadata: &BookMetadata,
+    chapters: &[(String, f64, f64)],
+    cover: Option<&Cover>,
+) -> Result<(), String> {
+    let write = |name: &str, contents: &str| {
+        fs::write(dir.join(name), contents).map_err(|e| format!("Failed to write {}: {}", name, e))
+    };
+    let description = metadata
+        .description
+        .as_deref()
+        .map(html_to_text)
+        .filter(|text| !text.is_empty());
+    let year = metadata
+        .date
+        .as_ref()
+        .map(|date| date.chars().take(4).collect::<String>());
+    let asin = metadata.identifiers.iter().find_map(|identifier| {
+        let scheme = identifier.scheme.as_deref()?.to_lowercase();
+        matches!(scheme.as_str(), "asin" | "mobi-asin" | "amazon").then(|| identifier.value.clone())
+    });
+    let chapter_list: Vec<serde_json::Value> = chapters
+        .iter()
+        .enumerate()
+        .map(|(id, (title, start, end))| {
+            serde_json::json!({
+                "id": id,
+                "start": start / 1000.0,
+                "end": end / 1000.0,
+                "title": title,
+            })
+        })
+        .collect();
+    let series: Vec<String> = metadata.series_label().into_iter().collect();
+
+    let json = serde_json::json!({
+        "tags": [],
+        "chapters": chapter_list,
+        "title": html_to_text(&metadata.title),
+        "subtitle": metadata.subtitle,
+        "authors": metadata.authors,
+        "narrators": metadata.narrators,
+        "series": series,
+        "genres": metadata.subjects,
+        "publishedYear": year,
+        "publishedDate": metadata.date,
+        "publisher": metadata.publisher,
+        "description": description,
+        "isbn": metadata.isbn(),
+        "asin": asin,
+        "language": metadata.language,
+        "explicit": false,
+        "abridged": false,
+    });
+    let pretty = serde_json::to_string_pretty(&json).map_err(|e| e.to_string())?;
+    write("metadata.json", &pretty)?;
+
+    // The older key=value format; list values are JSON arrays, the description gets its own section
+    let mut abs = String::from(";ABMETADATA2\n#audiobookshelf v2.2.0\n\nmedia=book\n");
+    for key in [
+        "tags",
+        "chapters",
+        "title",
+        "subtitle",
+        "authors",
+        "narrators",
+        "series",
+        "genres",
+        "publishedYear",
+        "publishedDate",
+        "publisher",
+        "isbn",
+        "asin",
+        "language",
+        "explicit",
+        "abridged",
+    ] {
+        let value = match &json[key] {
+            serde_json::Value::Null => String::new(),
+            serde_json::Value::String(text) => text.clone(),
+            other => other.to_string(),
+        };
+        abs.push_str(&format!("{}={}\n", key, value));
+    }
+    if let Some(description) = &description {
+        abs.push_str(&format!("\n[description]\n{}\n", description));
+    }
+    write("metadata.abs", &abs)?;
+
+    if let Some(description) = &description {
+        write("desc.txt", description)?;
+    }
+    if !metadata.narrators.is_empty() {
+        write("reader.txt", &metadata.narrators.join(", "))?;
+    }
+    if let Some(cover) = cover {
+        let extension = match cover.mime_type() {
+            "image/png" => "png",
+            _ => "jpg",
+        };
+        let cover_file = dir.join(format!("cover.{}", extension));
+        fs::copy(&cover.path, &cover_file)
+            .map_err(|e| format!("Failed to write {}: {}", cover_file.display(), e))?;
+    }
+    Ok(())
+}
+
+// Namespaces of the Dublin Core elements and the OPF <meta> elements
+const DC_NAMESPACE: &str = "http://purl.org/dc/elements/1.1/";
+const OPF_NAMESPACE: &str = "http://www.idpf.org/2007/opf";
+
+/// An identifier such as an ISBN, UUID or ASIN with its scheme, if the OPF names one
+#[derive(Clone, Debug, PartialEq)]
+pub struct Identifier {
+    pub scheme: Option<String>,
+    pub value: String,
+}
+
+/// Metadata of a book as read from its OPF package document
+#[derive(Clone, Debug, Default)]
+pub struct BookMetadata {
+    pub title: String,
+    pub subtitle: Option<String>,
+    pub authors: Vec<String>,
+    pub narrators: Vec<String>,
+    pub translators: Vec<String>,
+    pub illustrators: Vec<String>,
+    pub editors: Vec<String>,
+    pub date: Option<String>,
+    pub description: Option<String>,
+    pub language: Option<String>,
+    pub publisher: Option<String>,
+    pub subjects: Vec<String>,
+    pub identifiers: Vec<Identifier>,
+    pub series: Option<String>,
+    pub series_index: Option<String>,
+    /// Part number and part count when the book is split into several files
+    pub part: Option<(usize, usize)>,
+    /// Problems found while reading the metadata, reported to the user before tagging
+    pub warnings: Vec<String>,
+}
+
+impl BookMetadata {
+    /// Fills in what the OPF didn't provide. The title falls back to the one found in the
+    /// book itself (or its file name); other missing fields are left out of the tags.
+    pub fn fill_missing(&mut self, book_title: Option<&str>) {
+        if self.title.is_empty() {
+            match book_title.filter(|title| !title.trim().is_empty()) {
+                Some(title) => {
+                    self.title = title.trim().to_string();
+                    self.warnings
+                        .push(format!("No title in metadata, using \"{}\"", self.title));
+                }
+                None => self
+                    .warnings
+                    .push("No title found for the book".to_string()),
+            }
+        }
+        if self.authors.is_empty() {
+            self.warnings.push("No author in metadata".to_string());
+        }
+        if self.language.is_none() {
+            self.warnings.push("No language in metadata".to_string());
+        }
+        if self.date.is_none() {
+            self.warnings
+                .push("No publication date in metadata".to_string());
+        }
+        if self.description.is_none() {
+            self.warnings.push("No description in metadata".to_string());
+        }
+    }
+
+    /// Sets a field from a `key=value` override. List fields take several values separated
+    /// by ';', and an empty value clears the field.
+    pub fn apply_override(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let value = value.trim();
+        let single = || Some(value.to_string()).filter(|value| !value.is_empty());
+        let list = || {
+            value
+                .split(';')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect::<Vec<_>>()
+        };
+
+        match key.trim().to_lowercase().replace('-', "_").as_str() {
+            "title" => self.title = value.to_string(),
+            "subtitle" => self.subtitle = single(),
+            "author" | "authors" | "artist" => self.authors = list(),
+            "narrator" | "narrators" | "reader" => self.narrators = list(),
+            "translator" | "translators" => self.translators = list(),
+            "illustrator" | "illustrators" => self.illustrators = list(),
+            "editor" | "editors" => self.editors = list(),
+            "date" | "year" => self.date = single(),
+            "description" => self.description = single(),
+            "language" => self.language = single(),
+            "publisher" => self.publisher = single(),
+            "genre" | "genres" | "subject" | "subjects" => self.subjects = list(),
+            "series" => self.series = single(),
+            "series_index" | "series_part" => self.series_index = single(),
+            "isbn" => {
+                self.identifiers.retain(|identifier| {
+                    !identifier
+                        .scheme
+                        .as_deref()
+                        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("isbn"))
+                        && !identifier.value.to_lowercase().starts_with("urn:isbn:")
+                });
+                if let Some(isbn) = single() {
+                    self.identifiers.insert(
+                        0,
+                        Identifier {
+                            scheme: Some("ISBN".to_string()),
+                            value: isbn,
+                        },
+                    );
+                }
+            }
+            other => return Err(format!("Unknown metadata field '{}'", other)),
+        }
+        Ok(())
+    }
+
+    /// The title of this file, including the part number of split books
+    pub fn full_title(&self) -> String {
+        match self.part {
+            Some((part, _)) => format!("{} - Part {}", self.title, part),
+            None => self.title.clone(),
+        }
+    }
+
+    /// ISBN from the identifiers, with the "urn:isbn:" prefix removed
+    pub fn isbn(&self) -> Option<String> {
+        self.identifiers.iter().find_map(|identifier| {
+            let value = identifier.value.trim();
+            let is_isbn_scheme = identifier
+                .scheme
+                .as_deref()
+                .is_some_and(|scheme| scheme.eq_ignore_ascii_case("isbn"));
+            let lower = value.to_lowercase();
+
+            if let Some(isbn) = lower.strip_prefix("urn:isbn:") {
+                Some(isbn.to_uppercase())
+            } else if is_isbn_scheme {
+                Some(value.to_string())
+            } else {
+                // Bare ISBN-10/13 without a scheme
+                let digits: String = value.chars().filter(|c| *c != '-').collect();
+                let looks_like_isbn = (digits.len() == 10 || digits.len() == 13)
+                    && digits
+                        .chars()
+                        .all(|c| c.is_ascii_digit() || c == 'X' || c == 'x');
+                looks_like_isbn.then(|| value.to_string())
+            }
+        })
+    }
+
+    /// Series with its position, e.g. "The Expanse #3"
+    pub fn series_label(&self) -> Option<String> {
+        let series = self.series.as_ref()?;
+        Some(match &self.series_index {
+            Some(index) => format!("{} #{}", series, index),
+            None => series.clone(),
+        })
+    }
+
+    /// FFmpeg metadata keys for the output file. For MP4 most end up in the matching
+    /// atoms: artist/album_artist for authors, composer for narrators, grouping for the
+    /// series. FFmpeg has no atom for the ones in `FREEFORM_TAGS`, `add_freeform_tags`
+    /// writes those.
+    pub fn tags(&self) -> Vec<(String, String)> {
+        let mut tags = vec![
+            ("title".to_string(), self.full_title()),
+            ("album".to_string(), self.title.clone()),
+        ];
+        let mut push = |key: &str, value: String| {
+            if !value.is_empty() {
+                tags.push((key.to_string(), value));
+            }
+        };
+
+        push("date", self.date.clone().unwrap_or_default());
+        // Full description in the ldes atom (FFmpeg calls it synopsis), a short one in desc
+        if let Some(description) = &self.description {
+            let description = html_to_text(description);
+            push(
+                "description",
+                truncate_text(&description, SHORT_DESCRIPTION_LENGTH),
+            );
+            push("synopsis", description);
+        }
+        push("language", self.language.clone().unwrap_or_default());
+        push("artist", self.authors.join(", "));
+        push("album_artist", self.authors.join(", "));
+        push("composer", self.narrators.join(", "));
+        push("genre", self.subjects.join(", "));
+        push("publisher", self.publisher.clone().unwrap_or_default());
+        push("grouping", self.series_label().unwrap_or_default());
+        push("isbn", self.isbn().unwrap_or_default());
+        push("translator", self.translators.join(", "));
+        push("illustrator", self.illustrators.join(", "));
+        push("editor", self.editors.join(", "));
+        if let Some(subtitle) = &self.subtitle {
+            push("subtitle", subtitle.clone());
+        }
+        if let Some((part, parts)) = self.part {
+            push("disc", format!("{}/{}", part, parts));
+        }
+        // Titles and names can carry markup and entities too
+        tags.into_iter()
+            .map(|(key, value)| match key.as_str() {
+                "description" | "synopsis" => (key, value),
+                _ => (key, html_to_text(&value)),
+            })
+            .collect()
+    }
+}
+
+fn is_dc(element: &Element, name: &str) -> bool {
+    element.name == name
+        && (element.namespace.as_deref() == Some(DC_NAMESPACE)
+            || element.prefix.as_deref() == Some("dc"))
+}
+
+fn is_opf_meta(element: &Element) -> bool {
+    element.name == "meta" && matches!(element.namespace.as_deref(), Some(OPF_NAMESPACE) | None)
+}
+
+// Children of <metadata>, looking through the <dc-metadata>/<x-metadata> wrappers of old OPFs
+fn metadata_elements(metadata_node: &Element) -> Vec<&Element> {
+    let mut elements = Vec::new();
+    for child in metadata_node
+        .children
+        .iter()
+        .filter_map(|node| node.as_element())
+    {
+        if child.name == "dc-metadata" || child.name == "x-metadata" {
+            elements.extend(metadata_elements(child));
+        } else {
+            elements.push(child);
+        }
+    }
+    elements
+}
+
+// EPUB3 refinements: <meta refines="#id" property="...">value</meta>
+fn refinement(elements: &[&Element], id: Option<&String>, property: &str) -> Option<String> {
+    let target = format!("#{}", id?);
+    elements
+        .iter()
+        .filter(|elem| is_opf_meta(elem))
+        .find(|elem| {
+            elem.attributes.get("refines") == Some(&target)
+                && elem.attributes.get("property").map(String::as_str) == Some(property)
+        })
+        .map(|elem| get_text_from_element(elem).trim().to_string())
+}
+
+// OPF2 <meta name="..." content="..."/>
+fn named_meta(elements: &[&Element], name: &str) -> Option<String> {
+    elements
+        .iter()
+        .filter(|elem| is_opf_meta(elem))
+        .find(|elem| elem.attributes.get("name").map(String::as_str) == Some(name))
+        .and_then(|elem| elem.attributes.get("content"))
+        .map(|content| content.trim().to_string())
+        .filter(|content| !content.is_empty())
+}
+
+pub fn get_metadata(file_path: &str) -> BookMetadata {
+    let mut metadata = BookMetadata::default();
 
     // Open the file and create a buffered reader
-    let file = File::open(file_path).expect("Unable to open file");
+    let file = match File::open(file_path) {
+        Ok(file) => file,
+        Err(e) => {
+            metadata
+                .warnings
+                .push(format!("Unable to open {}: {}", file_path, e));
+            return metadata;
+        }
+    };
     let reader = BufReader::new(file);
 
     // Parse the XML file
-    let root: Element = Element::parse(reader).expect("Unable to parse XML");
+    let root: Element = match Element::parse(reader) {
+        Ok(root) => root,
+        Err(e) => {
+            metadata
+                .warnings
+                .push(format!("Unable to parse {}: {}", file_path, e));
+            return metadata;
+        }
+    };
 
     // Find relevant elements inside the metadata
-    if let Some(metadata_node) = root.get_child("metadata") {
-        let title = metadata_node
-            .get_child("title")
-            .map(|t| get_text_from_element(t))
-            .unwrap_or_else(|| "Title not found".to_string());
-
-        let date = metadata_node
-            .get_child("date")
-            .map(|d| get_text_from_element(d))
-            .unwrap_or_else(|| "Date not found".to_string());
-
-        let description = metadata_node
-            .get_child("description")
-            .map(|desc| get_text_from_element(desc))
-            .unwrap_or_else(|| "Description not found".to_string());
-
-        let language = metadata_node
-            .get_child("language")
-            .map(|lang| get_text_from_element(lang))
-            .unwrap_or_else(|| "Language not found".to_string());
-
-        // Collecting all creators (authors)
-        let authors: Vec<String> = metadata_node
-            .children
+    let Some(metadata_node) = root.get_child("metadata") else {
+        metadata
+            .warnings
+            .push(format!("No <metadata> element in {}", file_path));
+        return metadata;
+    };
+    let elements = metadata_elements(metadata_node);
+    let text_of = |name: &str| {
+        elements
             .iter()
-            .filter_map(|child| {
-                if let XMLNode::Element(elem) = child {
-                    if elem.name == "creator" {
-                        Some(get_text_from_element(elem))
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            })
-            .collect();
+            .find(|elem| is_dc(elem, name))
+            .map(|elem| get_text_from_element(elem).trim().to_string())
+    };
 
-        // Insert metadata into the HashMap
-        metadata_map.insert("title".to_string(), title);
-        metadata_map.insert("date".to_string(), date);
-        metadata_map.insert("description".to_string(), description);
-        metadata_map.insert("language".to_string(), language);
-        metadata_map.insert("author".to_string(), authors.join(", "));
+    // EPUB3 can have several titles told apart by title-type
+    for elem in elements.iter().filter(|elem| is_dc(elem, "title")) {
+        let text = get_text_from_element(elem).trim().to_string();
+        match refinement(&elements, elem.attributes.get("id"), "title-type").as_deref() {
+            Some("subtitle") => metadata.subtitle = Some(text),
+            _ if metadata.title.is_empty() => metadata.title = text,
+            _ => {}
+        }
+    }
+
+    // Empty elements count as missing
+    metadata.date = text_of("date").filter(|date| !date.is_empty());
+    metadata.description = text_of("description").filter(|description| !description.is_empty());
+    metadata.language = text_of("language").filter(|language| !language.is_empty());
+    metadata.publisher = text_of("publisher").filter(|publisher| !publisher.is_empty());
+
+    // Creators and contributors, sorted by their MARC relator role: opf:role in OPF2,
+    // a refining <meta property="role"> in OPF3
+    for elem in &elements {
+        let is_creator = is_dc(elem, "creator");
+        if !is_creator && !is_dc(elem, "contributor") {
+            continue;
+        }
+        let name = get_text_from_element(elem).trim().to_string();
+        if name.is_empty() {
+            continue;
+        }
+        let role = elem
+            .attributes
+            .get("role")
+            .cloned()
+            .or_else(|| refinement(&elements, elem.attributes.get("id"), "role"))
+            .unwrap_or_else(|| if is_creator { "aut" } else { "ctb" }.to_string());
+
+        match role.to_lowercase().as_str() {
+            "aut" => metadata.authors.push(name),
+            "nrr" | "spk" => metadata.narrators.push(name),
+            "trl" => metadata.translators.push(name),
+            "ill" | "art" => metadata.illustrators.push(name),
+            "edt" => metadata.editors.push(name),
+            _ => {}
+        }
     }
 
-    metadata_map // Return the HashMap with metadata
+    for elem in &elements {
+        if is_dc(elem, "subject") {
+            let subject = get_text_from_element(elem).trim().to_string();
+            if !subject.is_empty() {
+                metadata.subjects.push(subject);
+            }
+        } else if is_dc(elem, "identifier") {
+            let value = get_text_from_element(elem).trim().to_string();
+            let scheme =
+                elem.attributes.get("scheme").cloned().or_else(|| {
+                    refinement(&elements, elem.attributes.get("id"), "identifier-type")
+                });
+            if !value.is_empty() {
+                metadata.identifiers.push(Identifier { scheme, value });
+            }
+        }
+    }
+
+    // Series: EPUB3 collections first, then Calibre's OPF2 meta tags
+    let collection = elements.iter().find(|elem| {
+        is_opf_meta(elem)
+            && elem.attributes.get("property").map(String::as_str) == Some("belongs-to-collection")
+            && refinement(&elements, elem.attributes.get("id"), "collection-type")
+                .is_none_or(|kind| kind == "series")
+    });
+    if let Some(collection) = collection {
+        metadata.series = Some(get_text_from_element(collection).trim().to_string());
+        metadata.series_index =
+            refinement(&elements, collection.attributes.get("id"), "group-position");
+    } else {
+        metadata.series = named_meta(&elements, "calibre:series");
+        // Calibre stores the index as a float, "3.0" reads better as "3"
+        metadata.series_index =
+            named_meta(&elements, "calibre:series_index").map(|index| match index.parse::<f64>() {
+                Ok(number) if number.fract() == 0.0 => format!("{}", number as i64),
+                _ => index,
+            });
+    }
+
+    metadata
+}
+
+/// The href of the cover image in an OPF package, relative to the OPF: the EPUB3
+/// `cover-image` manifest item, the OPF2 `<meta name="cover">` item or a calibre
+/// style `<reference type="cover">` pointing at an image
+pub fn cover_href(package: &Element) -> Option<String> {
+    let is_image = |item: &Element| {
+        item.attributes
+            .get("media-type")
+            .is_some_and(|media_type| media_type.starts_with("image/"))
+    };
+    let items: Vec<&Element> = package
+        .get_child("manifest")
+        .map(|manifest| {
+            manifest
+                .children
+                .iter()
+                .filter_map(|node| node.as_element())
+                .filter(|elem| elem.name == "item")
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let cover_image = items.iter().find(|item| {
+        item.attributes
+            .get("properties")
+            .is_some_and(|properties| properties.split_whitespace().any(|p| p == "cover-image"))
+    });
+    let cover_meta = || {
+        let elements = metadata_elements(package.get_child("metadata")?);
+        let id = named_meta(&elements, "cover")?;
+        items
+            .iter()
+            .find(|item| is_image(item) && item.attributes.get("id") == Some(&id))
+    };
+    if let Some(item) = cover_image.or_else(cover_meta) {
+        return item.attributes.get("href").cloned();
+    }
+
+    package
+        .get_child("guide")?
+        .children
+        .iter()
+        .filter_map(|node| node.as_element())
+        .filter(|elem| elem.attributes.get("type").map(String::as_str) == Some("cover"))
+        .filter_map(|elem| elem.attributes.get("href"))
+        .find(|href| {
+            let lower = href.to_lowercase();
+            [".jpg", ".jpeg", ".png", ".gif", ".webp"]
+                .iter()
+                .any(|ext| lower.ends_with(ext))
+        })
+        .cloned()
+}
+
+/// Finds the cover image an OPF file points to, if it exists on disk
+pub fn find_cover(opf_path: &str) -> Option<String> {
+    let file = File::open(opf_path).ok()?;
+    let package = Element::parse(BufReader::new(file)).ok()?;
+    let href = cover_href(&package)?;
+    let path = Path::new(opf_path)
+        .parent()
+        .unwrap_or(Path::new(""))
+        .join(href.replace("%20", " "));
+    path.is_file().then(|| path.to_string_lossy().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn html_to_text_decodes_entities_and_keeps_paragraphs() {
+        assert_eq!(
+            html_to_text(
+                "<p>Tom &amp; Jerry&#8217;s <b>big</b>\n  day</p><p>Part&nbsp;two<br/>line</p>"
+            ),
+            "Tom & Jerry\u{2019}s big day\n\nPart two\n\nline"
+        );
+        assert_eq!(
+            html_to_text("<ul><li>One</li><li>Two</li></ul>"),
+            "One\n\nTwo"
+        );
+        // Plain text is left as it is, apart from the whitespace
+        assert_eq!(html_to_text("  Just   text "), "Just text");
+        assert_eq!(html_to_text("Fish &lt; chips"), "Fish < chips");
+        assert_eq!(html_to_text(""), "");
+    }
+
+    #[test]
+    fn tags_keep_the_full_description_in_synopsis() {
+        let metadata = BookMetadata {
+            title: "A &amp; B".to_string(),
+            description: Some(format!("<p>{}</p>", "word ".repeat(100))),
+            ..BookMetadata::default()
+        };
+        let tags = metadata.tags();
+        let tag = |key: &str| {
+            tags.iter()
+                .find(|(tag, _)| tag == key)
+                .map(|(_, value)| value.clone())
+                .unwrap()
+        };
+        assert_eq!(tag("title"), "A & B");
+        assert_eq!(tag("synopsis"), "word ".repeat(100).trim_end());
+        let short = tag("description");
+        assert!(short.chars().count() <= SHORT_DESCRIPTION_LENGTH);
+        assert!(short.ends_with("word\u{2026}"));
+    }
+
+    // Every tag written to an M4B comes back from the muxed file, the ones FFmpeg has no
+    // MP4 atom for included
+    #[test]
+    fn m4b_tags_are_read_back() {
+        if Command::new("ffmpeg").arg("-version").output().is_err() {
+            println!("FFmpeg not found, skipped");
+            return;
+        }
+        let dir = std::env::temp_dir().join(format!("edgeab-tags-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        let audio = dir.join("book.m4a").to_string_lossy().to_string();
+        let silence = Command::new("ffmpeg")
+            .args(["-y", "-f", "lavfi", "-i", "anullsrc=r=24000:cl=mono"])
+            .args(["-t", "1", "-c:a", "aac", &audio])
+            .output()
+            .unwrap();
+        assert!(silence.status.success());
+
+        let mut metadata = BookMetadata {
+            title: "Tagged".to_string(),
+            subtitle: Some("A Test".to_string()),
+            authors: strings(&["Ann Author"]),
+            narrators: strings(&["Ned Narrator"]),
+            translators: strings(&["Tia", "Tom"]),
+            illustrators: strings(&["Ivy"]),
+            editors: strings(&["Ed"]),
+            language: Some("en".to_string()),
+            publisher: Some("Pub House".to_string()),
+            series: Some("Series".to_string()),
+            series_index: Some("2".to_string()),
+            part: Some((1, 2)),
+            ..BookMetadata::default()
+        };
+        metadata.apply_override("isbn", "9780000000002").unwrap();
+        let book = dir.join("Tagged.m4b").to_string_lossy().to_string();
+        add_metadata(&audio, &book, &metadata, None, OutputFormat::M4b);
+
+        let tags = read_tags(&book).unwrap();
+        assert_eq!(tags.title, "Tagged");
+        assert_eq!(tags.subtitle.as_deref(), Some("A Test"));
+        assert_eq!(tags.authors, metadata.authors);
+        assert_eq!(tags.narrators, metadata.narrators);
+        assert_eq!(tags.translators, metadata.translators);
+        assert_eq!(tags.illustrators, metadata.illustrators);
+        assert_eq!(tags.editors, metadata.editors);
+        assert_eq!(tags.language.as_deref(), Some("en"));
+        assert_eq!(tags.publisher.as_deref(), Some("Pub House"));
+        assert_eq!(tags.isbn().as_deref(), Some("9780000000002"));
+        assert_eq!(tags.series_label().as_deref(), Some("Series #2"));
+        assert_eq!(tags.part, Some((1, 2)));
+        fs::remove_dir_all(&dir).ok();
+    }
 }
@@ -0,0 +1,271 @@
+// src/naming.rs
+use crate::metdata::{html_to_text, BookMetadata};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_TEMPLATE: &str = "{title}";
+
+// Longest file or folder name most filesystems accept is 255 bytes, leave room for " (2).m4b"
+const MAX_SEGMENT_LENGTH: usize = 200;
+
+/// Where the finished audiobook goes: a base directory and a path template such as
+/// `{author}/{series}/{series_index} - {title}`
+#[derive(Clone, Debug)]
+pub struct OutputNaming {
+    pub dir: PathBuf,
+    pub template: String,
+}
+
+impl OutputNaming {
+    /// Output path for the book (without extension). Placeholders with no value are
+    /// left out, along with the separators around them, and a split part without
+    /// `{part}` in the template gets " - Part N" added to its name.
+    pub fn path_for(&self, metadata: &BookMetadata) -> PathBuf {
+        let mut template = self.template.trim().to_string();
+        // The extension comes from the output format
+        for extension in [".m4b", ".m4a", ".mp3", ".opus", ".ogg"] {
+            if let Some(stripped) = template.strip_suffix(extension) {
+                template = stripped.to_string();
+                break;
+            }
+        }
+        if metadata.part.is_some() && !template.contains("{part}") {
+            template.push_str(" - Part {part}");
+        }
+
+        let segments: Vec<String> = template
+            .split(['/', '\\'])
+            .map(|segment| sanitize(&fill_placeholders(segment, metadata)))
+            .filter(|segment| !segment.is_empty())
+            .collect();
+
+        let mut path = self.dir.clone();
+        if segments.is_empty() {
+            path.push(sanitize(&html_to_text(&metadata.full_title())));
+        }
+        for segment in segments {
+            path.push(segment);
+        }
+        path
+    }
+
+    /// The same naming with every book in a folder of its own, as the sidecar files of
+    /// Audiobookshelf and Plex need: a `{title}` folder is added unless the last folder of
+    /// the template already has the title in it
+    pub fn with_book_folder(&self) -> OutputNaming {
+        let template = self.template.trim();
+        let (folders, name) = match template.rfind(['/', '\\']) {
+            Some(end) => (&template[..end], &template[end + 1..]),
+            None => ("", template),
+        };
+        let last_folder = folders.rsplit(['/', '\\']).next().unwrap_or("");
+        if last_folder.contains("{title}") {
+            return self.clone();
+        }
+        let template = if folders.is_empty() {
+            format!("{{title}}/{}", name)
+        } else {
+            format!("{}/{{title}}/{}", folders, name)
+        };
+        OutputNaming {
+            dir: self.dir.clone(),
+            template,
+        }
+    }
+}
+
+const PLACEHOLDERS: [&str; 11] = [
+    "title",
+    "subtitle",
+    "author",
+    "narrator",
+    "series",
+    "series_index",
+    "part",
+    "year",
+    "language",
+    "publisher",
+    "isbn",
+];
+
+fn placeholder(name: &str, metadata: &BookMetadata) -> Option<String> {
+    let value = match name {
+        "title" => Some(metadata.title.clone()),
+        "subtitle" => metadata.subtitle.clone(),
+        "author" => Some(metadata.authors.join(" & ")),
+        "narrator" => Some(metadata.narrators.join(" & ")),
+        "series" => metadata.series.clone(),
+        "series_index" => metadata.series_index.clone(),
+        "part" => metadata.part.map(|(part, _)| part.to_string()),
+        "year" => metadata
+            .date
+            .as_ref()
+            .map(|date| date.chars().take(4).collect()),
+        "language" => metadata.language.clone(),
+        "publisher" => metadata.publisher.clone(),
+        "isbn" => metadata.isbn(),
+        _ => None,
+    }?;
+    // A value must never add folders of its own
+    let value = html_to_text(&value).replace(['/', '\\'], "-");
+    (!value.trim().is_empty()).then_some(value)
+}
+
+// Replaces {name} with its value; unknown names are kept as written
+fn fill_placeholders(segment: &str, metadata: &BookMetadata) -> String {
+    let mut result = String::new();
+    let mut rest = segment;
+    while let Some(start) = rest.find('{') {
+        result.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        let name = &rest[start + 1..start + end];
+        if PLACEHOLDERS.contains(&name) {
+            result.push_str(&placeholder(name, metadata).unwrap_or_default());
+        } else {
+            result.push_str(&rest[start..=start + end]);
+        }
+        rest = &rest[start + end + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Makes a single file or folder name safe on Windows, macOS and Linux
+pub fn sanitize(name: &str) -> String {
+    let mut cleaned = String::new();
+    for c in name.chars() {
+        match c {
+            ':' => cleaned.push_str(" - "),
+            '"' => cleaned.push('\''),
+            '/' | '\\' | '|' => cleaned.push('-'),
+            '*' | '?' | '<' | '>' => {}
+            // A tab or line break still separates words
+            c if c.is_whitespace() => cleaned.push(' '),
+            c if c.is_control() => {}
+            c => cleaned.push(c),
+        }
+    }
+
+    let mut cleaned = cleaned.split_whitespace().collect::<Vec<_>>().join(" ");
+    // "- - " runs are left over when a placeholder was empty
+    while cleaned.contains("- -") {
+        cleaned = cleaned.replace("- -", "-");
+    }
+    let mut cleaned = cleaned
+        .trim_matches(|c: char| c == '.' || c == '-' || c == '_' || c.is_whitespace())
+        .to_string();
+
+    if cleaned.len() > MAX_SEGMENT_LENGTH {
+        let mut end = MAX_SEGMENT_LENGTH;
+        while !cleaned.is_char_boundary(end) {
+            end -= 1;
+        }
+        cleaned.truncate(end);
+        cleaned = cleaned.trim_end().to_string();
+    }
+
+    // Device names Windows won't create files for
+    let stem = cleaned.split('.').next().unwrap_or("").to_uppercase();
+    let reserved = matches!(stem.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || ((stem.starts_with("COM") || stem.starts_with("LPT"))
+            && stem.len() == 4
+            && stem.chars().last().is_some_and(|c| c.is_ascii_digit()));
+    if reserved {
+        cleaned.push('_');
+    }
+    cleaned
+}
+
+/// `path` with `extension` added, or "name (2).ext", "name (3).ext", ... if that's taken
+pub fn unique_path(path: &Path, extension: &str) -> PathBuf {
+    let with_extension = |suffix: &str| {
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(suffix);
+        if !extension.is_empty() {
+            name.push(".");
+            name.push(extension);
+        }
+        path.with_file_name(name)
+    };
+
+    let candidate = with_extension("");
+    if !candidate.exists() {
+        return candidate;
+    }
+    (2..)
+        .map(|n| with_extension(&format!(" ({})", n)))
+        .find(|candidate| !candidate.exists())
+        .unwrap_or(candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn naming(template: &str) -> OutputNaming {
+        OutputNaming {
+            dir: PathBuf::from("out"),
+            template: template.to_string(),
+        }
+    }
+
+    #[test]
+    fn sanitize_makes_names_safe_everywhere() {
+        assert_eq!(sanitize("Dune: Messiah"), "Dune - Messiah");
+        assert_eq!(sanitize("Who? \"Me\" <3"), "Who 'Me' 3");
+        assert_eq!(sanitize("AC/DC | Live"), "AC-DC - Live");
+        assert_eq!(sanitize("  ..Hidden\tname.  "), "Hidden name");
+        assert_eq!(sanitize("Author -  - Title"), "Author - Title");
+        assert_eq!(sanitize("con"), "con_");
+        assert_eq!(sanitize("LPT1.txt"), "LPT1.txt_");
+        assert_eq!(sanitize("Console"), "Console");
+
+        let long = sanitize(&"é".repeat(150));
+        assert!(long.len() <= MAX_SEGMENT_LENGTH);
+        assert_eq!(long, "é".repeat(100));
+    }
+
+    #[test]
+    fn empty_placeholders_leave_no_separators() {
+        let mut metadata = BookMetadata {
+            title: "Dune".to_string(),
+            authors: vec!["Frank Herbert".to_string()],
+            ..BookMetadata::default()
+        };
+        let naming = naming("{author}/{series}/{series_index} - {title}.m4b");
+        assert_eq!(
+            naming.path_for(&metadata),
+            Path::new("out").join("Frank Herbert").join("Dune")
+        );
+
+        metadata.series = Some("Dune/Chronicles".to_string());
+        metadata.series_index = Some("1".to_string());
+        metadata.part = Some((2, 3));
+        assert_eq!(
+            naming.path_for(&metadata),
+            Path::new("out")
+                .join("Frank Herbert")
+                .join("Dune-Chronicles")
+                .join("1 - Dune - Part 2")
+        );
+    }
+
+    #[test]
+    fn book_folder_is_added_once() {
+        assert_eq!(
+            naming("{author}/{title}").with_book_folder().template,
+            "{author}/{title}/{title}"
+        );
+        assert_eq!(
+            naming("{title}").with_book_folder().template,
+            "{title}/{title}"
+        );
+        assert_eq!(
+            naming("{author}/{title} ({year})/{title}")
+                .with_book_folder()
+                .template,
+            "{author}/{title} ({year})/{title}"
+        );
+    }
+}
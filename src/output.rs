@@ -0,0 +1,111 @@
+// src/output.rs
+use crate::tts::TtsFormat;
+use clap::ValueEnum;
+
+/// Layout and codec of the finished audiobook
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// AAC audiobook with chapter markers
+    M4b,
+    /// MP3 with ID3v2 CHAP/CTOC chapter frames and an APIC cover
+    Mp3,
+    /// Ogg Opus with chapters stored as CHAPTERxxx comments
+    Opus,
+    /// A folder with one numbered AAC file per chapter
+    Chapters,
+}
+
+impl OutputFormat {
+    /// Extension of the intermediate chapter files and of the joined book
+    pub fn audio_extension(&self) -> &'static str {
+        match self {
+            OutputFormat::M4b | OutputFormat::Chapters => "m4a",
+            OutputFormat::Mp3 => "mp3",
+            OutputFormat::Opus => "ogg",
+        }
+    }
+
+    /// Extension of the finished file(s)
+    pub fn file_extension(&self) -> &'static str {
+        match self {
+            OutputFormat::M4b => "m4b",
+            OutputFormat::Mp3 => "mp3",
+            OutputFormat::Opus => "opus",
+            OutputFormat::Chapters => "m4a",
+        }
+    }
+
+    /// FFmpeg encoder used when no codec is given
+    pub fn default_codec(&self) -> &'static str {
+        match self {
+            OutputFormat::M4b | OutputFormat::Chapters => "aac",
+            OutputFormat::Mp3 => "libmp3lame",
+            OutputFormat::Opus => "libopus",
+        }
+    }
+
+    /// Bitrate used when none is given
+    pub fn default_bitrate(&self) -> &'static str {
+        match self {
+            OutputFormat::M4b | OutputFormat::Chapters => "69k",
+            OutputFormat::Mp3 => "64k",
+            OutputFormat::Opus => "48k",
+        }
+    }
+}
+
+/// Encoder options for the chapter files; unset values fall back to the output format defaults
+#[derive(Clone, Debug, Default)]
+pub struct EncoderSettings {
+    pub codec: Option<String>,
+    pub bitrate: Option<String>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u32>,
+}
+
+impl EncoderSettings {
+    /// Whether paragraphs can be joined without re-encoding: the backend already delivers
+    /// the output codec and nothing about the encoding was asked for
+    pub fn is_copy(&self, format: OutputFormat, source: TtsFormat) -> bool {
+        match self.codec.as_deref() {
+            Some("copy") => true,
+            Some(_) => false,
+            None => {
+                source.codec() == format.default_codec()
+                    && self.bitrate.is_none()
+                    && self.sample_rate.is_none()
+                    && self.channels.is_none()
+            }
+        }
+    }
+
+    /// FFmpeg arguments used when paragraphs are combined into a chapter
+    pub fn ffmpeg_args(&self, format: OutputFormat, source: TtsFormat) -> Vec<String> {
+        if self.is_copy(format, source) {
+            vec!["-c:a".to_string(), "copy".to_string()]
+        } else {
+            self.encode_args(format)
+        }
+    }
+
+    /// FFmpeg arguments that always re-encode, needed when filters run on the audio
+    pub fn encode_args(&self, format: OutputFormat) -> Vec<String> {
+        let codec = self.codec.as_deref().unwrap_or(format.default_codec());
+        let bitrate = self.bitrate.as_deref().unwrap_or(format.default_bitrate());
+        let mut args = vec![
+            "-c:a".to_string(),
+            codec.to_string(),
+            "-b:a".to_string(),
+            bitrate.to_string(),
+        ];
+        if let Some(sample_rate) = self.sample_rate {
+            args.push("-ar".to_string());
+            args.push(sample_rate.to_string());
+        }
+        if let Some(channels) = self.channels {
+            args.push("-ac".to_string());
+            args.push(channels.to_string());
+        }
+        args
+    }
+}
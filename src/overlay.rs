@@ -0,0 +1,626 @@
+// src/overlay.rs
+use crate::epub;
+use crate::metdata::html_to_text;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use xmltree::Element;
+use zip::write::SimpleFileOptions;
+
+/// A paragraph of the book and where it can be heard
+pub struct SpokenParagraph {
+    pub text: String,
+    /// Audio file of the chapter the paragraph is in
+    pub audio_file: String,
+    /// Start and end in ms within `audio_file`
+    pub start: f64,
+    pub end: f64,
+}
+
+// Elements a paragraph of the intermediate text can come from
+const BLOCK_ELEMENTS: [&str; 16] = [
+    "p",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "li",
+    "blockquote",
+    "div",
+    "dd",
+    "dt",
+    "td",
+    "th",
+    "figcaption",
+    "pre",
+];
+
+// How many elements past the last match a paragraph is looked for; skipped front
+// matter (copyright, contents) sits between the chapters
+const LOOKAHEAD: usize = 500;
+
+// A block element of an XHTML document and the text directly inside it
+struct Block {
+    /// Byte offset right after the element name in its start tag, where an id can go
+    insert_at: usize,
+    id: Option<String>,
+    raw_text: String,
+}
+
+/// Writes a copy of `source_epub` with EPUB3 media overlays: every paragraph of the
+/// XHTML that matches a spoken paragraph is linked to its clip in the chapter audio,
+/// which is added to the book. Returns how many elements were linked.
+pub fn write_read_along(
+    source_epub: &str,
+    paragraphs: &[SpokenParagraph],
+    output_path: &str,
+) -> Result<usize, String> {
+    let error = |e: io::Error| format!("Failed to read {}: {}", source_epub, e);
+    let file = File::open(source_epub).map_err(error)?;
+    let mut archive = zip::ZipArchive::new(BufReader::new(file)).map_err(|e| e.to_string())?;
+
+    let opf_path = epub::opf_path(&mut archive).map_err(error)?;
+    let opf_data = epub::read_entry(&mut archive, &opf_path).map_err(error)?;
+    let mut opf = String::from_utf8(opf_data).map_err(|e| e.to_string())?;
+    let package = Element::parse(opf.as_bytes()).map_err(|e| e.to_string())?;
+    if !package
+        .attributes
+        .get("version")
+        .is_some_and(|version| version.starts_with('3'))
+    {
+        eprintln!(
+            "Media overlays are an EPUB 3 feature, readers may ignore them in this EPUB 2 book"
+        );
+    }
+
+    // Spine documents in reading order, as (manifest id, href); the navigation
+    // document only repeats the headings
+    let manifest: HashMap<String, (String, String)> = package
+        .get_child("manifest")
+        .map(|manifest| child_elements(manifest, "item"))
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|item| {
+            !item
+                .attributes
+                .get("properties")
+                .is_some_and(|properties| properties.split_whitespace().any(|p| p == "nav"))
+        })
+        .filter_map(|item| {
+            let id = item.attributes.get("id")?.clone();
+            let href = item.attributes.get("href")?.clone();
+            let media_type = item
+                .attributes
+                .get("media-type")
+                .cloned()
+                .unwrap_or_default();
+            Some((id, (href, media_type)))
+        })
+        .collect();
+    let spine: Vec<(String, String)> = package
+        .get_child("spine")
+        .map(|spine| child_elements(spine, "itemref"))
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|itemref| {
+            let id = itemref.attributes.get("idref")?;
+            let (href, media_type) = manifest.get(id)?;
+            (media_type == "application/xhtml+xml").then(|| (id.clone(), href.clone()))
+        })
+        .collect();
+
+    let mut documents = Vec::new();
+    for (_, href) in &spine {
+        let entry = epub::resolve_href(&opf_path, href);
+        let data = epub::read_entry(&mut archive, &entry).map_err(error)?;
+        let xhtml = String::from_utf8_lossy(&data).to_string();
+        let blocks = find_blocks(&xhtml);
+        documents.push((entry, xhtml, blocks));
+    }
+
+    // Clip of every linked block, keyed by (document, block)
+    let all_blocks: Vec<(usize, usize, String)> = documents
+        .iter()
+        .enumerate()
+        .flat_map(|(doc, (_, _, blocks))| {
+            blocks
+                .iter()
+                .enumerate()
+                .map(move |(block, b)| (doc, block, normalize(&html_to_text(&b.raw_text))))
+        })
+        .collect();
+    let mut clips: HashMap<(usize, usize), (String, f64, f64)> = HashMap::new();
+    let mut cursor = 0;
+    for paragraph in paragraphs {
+        let wanted = normalize(&paragraph.text);
+        if wanted.len() < 2 {
+            continue;
+        }
+        let end = (cursor + LOOKAHEAD).min(all_blocks.len());
+        let found = all_blocks[cursor..end].iter().position(|(_, _, text)| {
+            !text.is_empty()
+                && (text.contains(&wanted)
+                    || (wanted.contains(text.as_str()) && text.len() * 2 >= wanted.len()))
+        });
+        let Some(offset) = found else {
+            continue;
+        };
+        cursor += offset;
+        let (doc, block, _) = &all_blocks[cursor];
+
+        // Several paragraphs of the text can come from one element
+        let clip = clips
+            .entry((*doc, *block))
+            .or_insert_with(|| (paragraph.audio_file.clone(), paragraph.start, paragraph.end));
+        if clip.0 == paragraph.audio_file {
+            clip.1 = clip.1.min(paragraph.start);
+            clip.2 = clip.2.max(paragraph.end);
+        }
+    }
+    if clips.is_empty() {
+        return Err("None of the spoken paragraphs were found in the EPUB".to_string());
+    }
+
+    // Overlays and audio go in a folder next to the OPF
+    let opf_dir = epub::resolve_href(&opf_path, "");
+    let in_opf_dir = |name: &str| match opf_dir.as_str() {
+        "" => name.to_string(),
+        dir => format!("{}/{}", dir, name),
+    };
+    let mut audio_files: Vec<String> = Vec::new();
+    let mut new_entries: Vec<(String, Vec<u8>)> = Vec::new();
+    let mut replaced: HashMap<String, String> = HashMap::new();
+    let mut manifest_items = Vec::new();
+    let mut metas = Vec::new();
+    let mut overlay_ids = Vec::new();
+    let mut total_duration = 0.0;
+    let mut linked = 0;
+
+    for (doc, (entry, xhtml, blocks)) in documents.iter().enumerate() {
+        let mut doc_clips: Vec<(usize, &(String, f64, f64))> = clips
+            .iter()
+            .filter(|((clip_doc, _), _)| *clip_doc == doc)
+            .map(|((_, block), clip)| (*block, clip))
+            .collect();
+        if doc_clips.is_empty() {
+            continue;
+        }
+        doc_clips.sort_by_key(|(block, _)| *block);
+
+        // Elements without an id get one so the overlay can point at them
+        let mut xhtml = xhtml.clone();
+        let mut ids = HashMap::new();
+        let mut insertions = Vec::new();
+        for (block, _) in &doc_clips {
+            let id = match &blocks[*block].id {
+                Some(id) => id.clone(),
+                None => {
+                    let id = format!("mo-{}-{}", doc + 1, block + 1);
+                    insertions.push((blocks[*block].insert_at, format!(" id=\"{}\"", id)));
+                    id
+                }
+            };
+            ids.insert(*block, id);
+        }
+        for (at, attribute) in insertions.into_iter().rev() {
+            xhtml.insert_str(at, &attribute);
+        }
+        replaced.insert(entry.clone(), xhtml);
+
+        let (manifest_id, href) = &spine[doc];
+        let overlay_id = format!("mo-overlay-{}", doc + 1);
+        let overlay_name = format!("mo/overlay_{}.smil", doc + 1);
+        let mut smil = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <smil xmlns=\"http://www.w3.org/ns/SMIL\" xmlns:epub=\"http://www.idpf.org/2007/ops\" version=\"3.0\">\n\
+             <body>\n",
+        );
+        smil.push_str(&format!(
+            "<seq id=\"seq-{}\" epub:textref=\"../{}\">\n",
+            doc + 1,
+            escape(href)
+        ));
+        let mut duration = 0.0;
+        for (i, (block, (audio_file, start, end))) in doc_clips.iter().enumerate() {
+            let audio_index = match audio_files.iter().position(|file| file == audio_file) {
+                Some(index) => index,
+                None => {
+                    audio_files.push(audio_file.clone());
+                    audio_files.len() - 1
+                }
+            };
+            let audio_name = audio_entry_name(audio_index, audio_file);
+            smil.push_str(&format!(
+                "<par id=\"par-{}-{}\">\n<text src=\"../{}#{}\"/>\n<audio src=\"{}\" clipBegin=\"{}\" clipEnd=\"{}\"/>\n</par>\n",
+                doc + 1,
+                i + 1,
+                escape(href),
+                escape(&ids[block]),
+                escape(&audio_name),
+                clock(*start),
+                clock(*end)
+            ));
+            duration += end - start;
+        }
+        smil.push_str("</seq>\n</body>\n</smil>\n");
+
+        linked += doc_clips.len();
+        total_duration += duration;
+        new_entries.push((in_opf_dir(&overlay_name), smil.into_bytes()));
+        manifest_items.push(format!(
+            "<item id=\"{}\" href=\"{}\" media-type=\"application/smil+xml\"/>",
+            overlay_id, overlay_name
+        ));
+        metas.push(format!(
+            "<meta property=\"media:duration\" refines=\"#{}\">{}</meta>",
+            overlay_id,
+            clock(duration)
+        ));
+        overlay_ids.push((manifest_id.clone(), overlay_id));
+    }
+
+    for (i, audio_file) in audio_files.iter().enumerate() {
+        let data =
+            fs::read(audio_file).map_err(|e| format!("Failed to read {}: {}", audio_file, e))?;
+        let name = audio_entry_name(i, audio_file);
+        let media_type = audio_media_type(audio_file).ok_or_else(|| {
+            format!(
+                "{} is not an EPUB core audio type, use M4B or MP3 output for read-along books",
+                audio_file
+            )
+        })?;
+        manifest_items.push(format!(
+            "<item id=\"mo-audio-{}\" href=\"mo/{}\" media-type=\"{}\"/>",
+            i + 1,
+            escape(&name),
+            media_type
+        ));
+        new_entries.push((in_opf_dir(&format!("mo/{}", name)), data));
+    }
+    metas.push(format!(
+        "<meta property=\"media:duration\">{}</meta>",
+        clock(total_duration)
+    ));
+    metas.push(
+        "<meta property=\"media:active-class\">-epub-media-overlay-active</meta>".to_string(),
+    );
+
+    opf = link_overlays(&opf, &overlay_ids);
+    opf = insert_before_close(&opf, "manifest", &manifest_items)?;
+    opf = insert_before_close(&opf, "metadata", &metas)?;
+    replaced.insert(opf_path.clone(), opf);
+
+    write_epub(&mut archive, output_path, &replaced, &new_entries)
+        .map_err(|e| format!("Failed to write {}: {}", output_path, e))?;
+    Ok(linked)
+}
+
+// MP3 and AAC in MP4 are the audio every EPUB 3 reader has to play
+fn audio_media_type(audio_file: &str) -> Option<&'static str> {
+    match Path::new(audio_file).extension()?.to_str()? {
+        "mp3" => Some("audio/mpeg"),
+        "m4a" | "mp4" => Some("audio/mp4"),
+        _ => None,
+    }
+}
+
+// Name of the audio in the book's mo folder; numbered, chapter files of different
+// folders can have the same name
+fn audio_entry_name(index: usize, audio_file: &str) -> String {
+    let name = Path::new(audio_file)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    format!("{:03}_{}", index + 1, name)
+}
+
+fn child_elements<'a>(parent: &'a Element, name: &str) -> Vec<&'a Element> {
+    parent
+        .children
+        .iter()
+        .filter_map(|node| node.as_element())
+        .filter(|elem| elem.name == name)
+        .collect()
+}
+
+// Finds the block elements in an XHTML document. The document is scanned as text so
+// it can be written back unchanged apart from the added ids.
+fn find_blocks(xhtml: &str) -> Vec<Block> {
+    let id_attribute = Regex::new(r#"\sid\s*=\s*["']([^"']*)["']"#).unwrap();
+    let mut blocks: Vec<Block> = Vec::new();
+    // Open elements and the block each one is
+    let mut open: Vec<(String, Option<usize>)> = Vec::new();
+    let mut pos = 0;
+
+    while let Some(offset) = xhtml[pos..].find('<') {
+        let start = pos + offset;
+        // Text belongs to the innermost open block
+        if let Some(block) = open.iter().rev().find_map(|(_, block)| *block) {
+            blocks[block].raw_text.push_str(&xhtml[pos..start]);
+        }
+
+        let rest = &xhtml[start..];
+        let skip_past = |end: &str| {
+            rest.find(end)
+                .map(|i| start + i + end.len())
+                .unwrap_or(xhtml.len())
+        };
+        if rest.starts_with("<!--") {
+            pos = skip_past("-->");
+            continue;
+        }
+        if rest.starts_with("<![CDATA[") {
+            pos = skip_past("]]>");
+            continue;
+        }
+        if rest.starts_with("<?") {
+            pos = skip_past("?>");
+            continue;
+        }
+        if rest.starts_with("<!") {
+            pos = skip_past(">");
+            continue;
+        }
+
+        let end = tag_end(xhtml, start);
+        let tag = &xhtml[start..end];
+        if let Some(name) = tag.strip_prefix("</") {
+            let name = local_name(name.trim_end_matches('>').trim());
+            if let Some(i) = open.iter().rposition(|(open_name, _)| open_name == name) {
+                open.truncate(i);
+            }
+        } else {
+            let name_length = tag[1..]
+                .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+                .unwrap_or(tag.len() - 1);
+            let name = local_name(&tag[1..1 + name_length]).to_lowercase();
+            if tag.ends_with("/>") {
+                if name == "br" {
+                    if let Some(block) = open.iter().rev().find_map(|(_, block)| *block) {
+                        blocks[block].raw_text.push(' ');
+                    }
+                }
+            } else {
+                let block = BLOCK_ELEMENTS.contains(&name.as_str()).then(|| {
+                    blocks.push(Block {
+                        insert_at: start + 1 + name_length,
+                        id: id_attribute.captures(tag).map(|c| c[1].to_string()),
+                        raw_text: String::new(),
+                    });
+                    blocks.len() - 1
+                });
+                open.push((name, block));
+            }
+        }
+        pos = end;
+    }
+    blocks
+}
+
+// Index just past the '>' closing the tag at `start`, skipping quoted attribute values
+fn tag_end(xhtml: &str, start: usize) -> usize {
+    let mut quote = None;
+    for (i, c) in xhtml[start..].char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '>') => return start + i + 1,
+            _ => {}
+        }
+    }
+    xhtml.len()
+}
+
+fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+// Letters and digits only, so whitespace, quotes and entities don't get in the way
+fn normalize(text: &str) -> String {
+    text.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+// SMIL clock value h:mm:ss.fff
+fn clock(ms: f64) -> String {
+    let ms = ms.max(0.0).round() as u64;
+    format!(
+        "{}:{:02}:{:02}.{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+// Adds media-overlay="..." to the manifest items of the linked documents
+fn link_overlays(opf: &str, overlay_ids: &[(String, String)]) -> String {
+    let item = Regex::new(r"<(?:[\w-]+:)?item\s[^>]*>").unwrap();
+    let id_attribute = Regex::new(r#"\sid\s*=\s*["']([^"']*)["']"#).unwrap();
+    let mut insertions = Vec::new();
+    for tag in item.find_iter(opf) {
+        let Some(id) = id_attribute
+            .captures(tag.as_str())
+            .map(|c| c[1].to_string())
+        else {
+            continue;
+        };
+        if let Some((_, overlay_id)) = overlay_ids.iter().find(|(item_id, _)| *item_id == id) {
+            let closing = if tag.as_str().ends_with("/>") { 2 } else { 1 };
+            insertions.push((
+                tag.end() - closing,
+                format!(" media-overlay=\"{}\"", overlay_id),
+            ));
+        }
+    }
+    let mut opf = opf.to_string();
+    for (at, attribute) in insertions.into_iter().rev() {
+        opf.insert_str(at, &attribute);
+    }
+    opf
+}
+
+// Inserts the elements before </name>, using the same namespace prefix as the close tag
+fn insert_before_close(opf: &str, name: &str, elements: &[String]) -> Result<String, String> {
+    let close = Regex::new(&format!(r"</([\w-]+:)?{}\s*>", name)).unwrap();
+    let found = close
+        .captures(opf)
+        .ok_or_else(|| format!("The OPF has no <{}> element", name))?;
+    let prefix = found.get(1).map(|p| p.as_str()).unwrap_or("");
+    let at = found.get(0).map(|m| m.start()).unwrap_or(0);
+
+    let mut inserted = String::new();
+    for element in elements {
+        // Both the opening and the closing tag need the prefix
+        let element = element
+            .replacen('<', &format!("<{}", prefix), 1)
+            .replace("</", &format!("</{}", prefix));
+        inserted.push_str("    ");
+        inserted.push_str(&element);
+        inserted.push('\n');
+    }
+    let mut opf = opf.to_string();
+    opf.insert_str(at, &inserted);
+    Ok(opf)
+}
+
+// Copies the archive with some entries replaced and new ones added; mimetype has to
+// come first and uncompressed
+fn write_epub<R: Read + io::Seek>(
+    archive: &mut zip::ZipArchive<R>,
+    output_path: &str,
+    replaced: &HashMap<String, String>,
+    new_entries: &[(String, Vec<u8>)],
+) -> io::Result<()> {
+    let to_io = |e: zip::result::ZipError| io::Error::other(e.to_string());
+    let stored = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    let deflated = SimpleFileOptions::default();
+    let mut writer = zip::ZipWriter::new(File::create(output_path)?);
+
+    writer.start_file("mimetype", stored).map_err(to_io)?;
+    writer.write_all(b"application/epub+zip")?;
+
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(to_io)?;
+        let name = entry.name().to_string();
+        if name == "mimetype" {
+            continue;
+        }
+        if entry.is_dir() {
+            writer.add_directory(name, deflated).map_err(to_io)?;
+            continue;
+        }
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        writer.start_file(name.as_str(), deflated).map_err(to_io)?;
+        match replaced.get(&name) {
+            Some(contents) => writer.write_all(contents.as_bytes())?,
+            None => writer.write_all(&data)?,
+        }
+    }
+    for (name, data) in new_entries {
+        // Audio is already compressed
+        let options = if name.ends_with(".smil") {
+            deflated
+        } else {
+            stored
+        };
+        writer.start_file(name.as_str(), options).map_err(to_io)?;
+        writer.write_all(data)?;
+    }
+    writer.finish().map_err(to_io)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An EPUB 3 with one chapter of two paragraphs, and audio files for them
+    fn fixture(dir: &Path, audio_names: [&str; 2]) -> (String, Vec<SpokenParagraph>) {
+        fs::create_dir_all(dir).unwrap();
+        let epub_path = dir.join("book.epub");
+        let mut zip = zip::ZipWriter::new(File::create(&epub_path).unwrap());
+        let options = SimpleFileOptions::default();
+        let entries = [
+            (
+                "META-INF/container.xml",
+                r#"<?xml version="1.0"?><container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container"><rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles></container>"#,
+            ),
+            (
+                "OEBPS/content.opf",
+                r#"<?xml version="1.0"?><package xmlns="http://www.idpf.org/2007/opf" version="3.0"><metadata></metadata><manifest><item id="ch1" href="ch1.xhtml" media-type="application/xhtml+xml"/></manifest><spine><itemref idref="ch1"/></spine></package>"#,
+            ),
+            (
+                "OEBPS/ch1.xhtml",
+                r#"<html xmlns="http://www.w3.org/1999/xhtml"><body><p>The first paragraph.</p><p>The second paragraph.</p></body></html>"#,
+            ),
+        ];
+        for (name, contents) in entries {
+            zip.start_file(name, options).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let paragraphs = ["The first paragraph.", "The second paragraph."]
+            .iter()
+            .zip(audio_names)
+            .map(|(text, audio_name)| {
+                let audio_file = dir.join(audio_name);
+                fs::create_dir_all(audio_file.parent().unwrap()).unwrap();
+                fs::write(&audio_file, audio_name).unwrap();
+                SpokenParagraph {
+                    text: text.to_string(),
+                    audio_file: audio_file.to_string_lossy().to_string(),
+                    start: 0.0,
+                    end: 1000.0,
+                }
+            })
+            .collect();
+        (epub_path.to_string_lossy().to_string(), paragraphs)
+    }
+
+    #[test]
+    fn audio_of_the_same_name_is_kept_apart() {
+        let dir = std::env::temp_dir().join(format!("edgeab-overlay-{}", std::process::id()));
+        let (epub_path, paragraphs) = fixture(&dir, ["a/chapter_0.mp3", "b/chapter_0.mp3"]);
+        let output = dir.join("read-along.epub").to_string_lossy().to_string();
+        assert_eq!(write_read_along(&epub_path, &paragraphs, &output), Ok(2));
+
+        let mut archive = zip::ZipArchive::new(File::open(&output).unwrap()).unwrap();
+        let first = epub::read_entry(&mut archive, "OEBPS/mo/001_chapter_0.mp3").unwrap();
+        let second = epub::read_entry(&mut archive, "OEBPS/mo/002_chapter_0.mp3").unwrap();
+        assert_eq!(first, b"a/chapter_0.mp3");
+        assert_eq!(second, b"b/chapter_0.mp3");
+        let smil = epub::read_entry(&mut archive, "OEBPS/mo/overlay_1.smil").unwrap();
+        let smil = String::from_utf8(smil).unwrap();
+        assert!(smil.contains("src=\"001_chapter_0.mp3\""));
+        assert!(smil.contains("src=\"002_chapter_0.mp3\""));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn audio_readers_may_not_play_is_refused() {
+        let dir = std::env::temp_dir().join(format!("edgeab-overlay-ogg-{}", std::process::id()));
+        let (epub_path, paragraphs) = fixture(&dir, ["chapter_0.ogg", "chapter_1.ogg"]);
+        let output = dir.join("read-along.epub").to_string_lossy().to_string();
+        assert!(write_read_along(&epub_path, &paragraphs, &output).is_err());
+        assert!(!Path::new(&output).exists());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,206 @@
+// src/postprocess.rs
+use crate::tts::SAMPLE_RATE;
+use regex::Regex;
+use std::fs;
+use std::process::{Command, Stdio};
+
+/// Optional processing applied while paragraphs are combined into chapters
+#[derive(Clone, Debug, Default)]
+pub struct PostProcess {
+    /// Integrated loudness target in LUFS for EBU R128 normalization
+    pub loudness: Option<f64>,
+    /// Trim leading and trailing silence from every paragraph
+    pub trim_silence: bool,
+    /// Light dynamic range compression
+    pub compress: bool,
+}
+
+/// Loudness values reported by FFmpeg's loudnorm filter
+#[derive(Clone, Debug)]
+pub struct Loudness {
+    /// Integrated loudness in LUFS
+    pub integrated: f64,
+    /// True peak in dBTP
+    pub true_peak: f64,
+    /// Loudness range in LU
+    pub range: f64,
+    pub threshold: f64,
+    pub offset: f64,
+}
+
+// Peak and range targets that suit spoken word
+const TRUE_PEAK: f64 = -1.5;
+const LOUDNESS_RANGE: f64 = 11.0;
+
+impl PostProcess {
+    pub fn is_active(&self) -> bool {
+        self.loudness.is_some() || self.trim_silence || self.compress
+    }
+
+    /// Filters that run before the loudness is measured
+    pub fn pre_filters(&self) -> Vec<String> {
+        let mut filters = Vec::new();
+        if self.compress {
+            filters.push(
+                "acompressor=threshold=-20dB:ratio=2.5:attack=20:release=250:makeup=2".to_string(),
+            );
+        }
+        filters
+    }
+
+    /// The complete filter chain for the chapter encode. `measured` comes from the first
+    /// loudnorm pass so the second pass can normalize linearly instead of dynamically.
+    pub fn filters(&self, measured: Option<&Loudness>, sample_rate: u32) -> Vec<String> {
+        let mut filters = self.pre_filters();
+        if let Some(target) = self.loudness {
+            let mut loudnorm = format!(
+                "loudnorm=I={}:TP={}:LRA={}",
+                target, TRUE_PEAK, LOUDNESS_RANGE
+            );
+            if let Some(measured) = measured {
+                loudnorm.push_str(&format!(
+                    ":measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true",
+                    measured.integrated,
+                    measured.true_peak,
+                    measured.range,
+                    measured.threshold,
+                    measured.offset
+                ));
+            }
+            loudnorm.push_str(":print_format=json");
+            filters.push(loudnorm);
+            // loudnorm works at 192 kHz internally
+            filters.push(format!("aresample={}", sample_rate));
+        }
+        filters
+    }
+}
+
+/// First loudnorm pass over a concat list: measures the chapter without writing anything
+pub fn measure_loudness(input_list: &str, post: &PostProcess) -> Result<Loudness, String> {
+    let filters = post.filters(None, SAMPLE_RATE).join(",");
+    let output = Command::new("ffmpeg")
+        .args(["-f", "concat", "-safe", "0", "-i", input_list, "-af"])
+        .arg(&filters)
+        .args(["-f", "null", "-"])
+        .stdout(Stdio::null())
+        .output()
+        .map_err(|e| format!("Failed to execute FFmpeg: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "Loudness measurement failed with status: {}",
+            output.status
+        ));
+    }
+
+    parse_loudnorm(&String::from_utf8_lossy(&output.stderr), "input")
+        .ok_or_else(|| "No loudness data in FFmpeg output".to_string())
+}
+
+/// Reads the JSON block loudnorm prints at the end of a run. `prefix` is "input" for the
+/// measured values or "output" for the values after normalization.
+pub fn parse_loudnorm(stderr: &str, prefix: &str) -> Option<Loudness> {
+    let json = &stderr[stderr.rfind('{')?..];
+    let re = Regex::new(r#""(\w+)"\s*:\s*"([^"]*)""#).unwrap();
+    // Silence is measured as -inf, which the second pass can't take
+    let value = |key: &str| -> Option<f64> {
+        re.captures_iter(json)
+            .find(|caps| &caps[1] == key)
+            .and_then(|caps| caps[2].parse::<f64>().ok())
+            .filter(|value| value.is_finite())
+    };
+
+    Some(Loudness {
+        integrated: value(&format!("{}_i", prefix))?,
+        true_peak: value(&format!("{}_tp", prefix))?,
+        range: value(&format!("{}_lra", prefix))?,
+        threshold: value(&format!("{}_thresh", prefix))?,
+        offset: value("target_offset").unwrap_or(0.0),
+    })
+}
+
+/// Cuts silence from the start and end of every paragraph. The trimmed audio is kept as
+/// PCM WAV so this doesn't add a lossy encode; the originals are removed.
+pub fn trim_silence(files: Vec<String>) -> Vec<String> {
+    let trim = "silenceremove=start_periods=1:start_threshold=-50dB:start_silence=0.05";
+    let filter = format!("{},areverse,{},areverse", trim, trim);
+    let mut trimmed_files = Vec::new();
+
+    for file in files {
+        let trimmed = match file.rsplit_once('.') {
+            Some((stem, _)) => format!("{}_trimmed.wav", stem),
+            None => format!("{}_trimmed.wav", file),
+        };
+        let status = Command::new("ffmpeg")
+            .args([
+                "-y",
+                "-i",
+                &file,
+                "-af",
+                &filter,
+                "-c:a",
+                "pcm_s16le",
+                &trimmed,
+            ])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+
+        match status {
+            Ok(status) if status.success() => {
+                fs::remove_file(&file).ok();
+                trimmed_files.push(trimmed);
+            }
+            _ => {
+                eprintln!("Failed to trim silence from {}, keeping it as is", file);
+                trimmed_files.push(file);
+            }
+        }
+    }
+    trimmed_files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The end of what FFmpeg writes to stderr for a loudnorm pass with print_format=json
+    const STDERR: &str = r#"size=N/A time=00:00:12.34 bitrate=N/A speed= 410x
+[Parsed_loudnorm_0 @ 0x5581]
+{
+	"input_i" : "-23.54",
+	"input_tp" : "-4.10",
+	"input_lra" : "5.20",
+	"input_thresh" : "-33.81",
+	"output_i" : "-18.02",
+	"output_tp" : "-1.50",
+	"output_lra" : "4.60",
+	"output_thresh" : "-28.25",
+	"normalization_type" : "dynamic",
+	"target_offset" : "0.02"
+}
+"#;
+
+    #[test]
+    fn parse_loudnorm_reads_the_values_with_the_prefix() {
+        let input = parse_loudnorm(STDERR, "input").unwrap();
+        assert_eq!(input.integrated, -23.54);
+        assert_eq!(input.true_peak, -4.1);
+        assert_eq!(input.range, 5.2);
+        assert_eq!(input.threshold, -33.81);
+        assert_eq!(input.offset, 0.02);
+
+        let output = parse_loudnorm(STDERR, "output").unwrap();
+        assert_eq!(output.integrated, -18.02);
+        assert_eq!(output.true_peak, -1.5);
+    }
+
+    #[test]
+    fn parse_loudnorm_needs_every_value() {
+        assert!(parse_loudnorm("no json here", "input").is_none());
+        assert!(parse_loudnorm(r#"{ "input_i" : "-23.0" }"#, "input").is_none());
+        let silent = STDERR.replace("\"-23.54\"", "\"-inf\"");
+        assert!(parse_loudnorm(&silent, "input").is_none());
+    }
+}
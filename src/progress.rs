@@ -0,0 +1,258 @@
+// src/progress.rs
+use clap::ValueEnum;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// How progress is shown
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// Progress bars and colored messages for a terminal
+    Bar,
+    /// Newline-delimited JSON events for other programs
+    Json,
+}
+
+// Set once stdout carries the JSON events
+static MESSAGES_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Where the messages for people go: stdout, or stderr once stdout carries the events
+pub fn messages_to_stderr() -> bool {
+    MESSAGES_TO_STDERR.load(Ordering::Relaxed)
+}
+
+/// Stdout as the event sink, every message printed with `say!` goes to stderr from now on
+pub fn stdout_events() -> Box<dyn Write + Send> {
+    MESSAGES_TO_STDERR.store(true, Ordering::Relaxed);
+    Box::new(std::io::stdout())
+}
+
+/// `println!` for messages to the user, which must stay off stdout while it carries
+/// the JSON events
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if $crate::progress::messages_to_stderr() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+pub(crate) use say;
+
+/// Counters of a running conversion, shared with whoever reports on it. With an event
+/// sink every change is also written out as one JSON object per line.
+pub struct Progress {
+    stage: Mutex<(String, Instant)>,
+    chapter: AtomicUsize,
+    chapters: AtomicUsize,
+    paragraphs: AtomicUsize,
+    paragraphs_done: AtomicUsize,
+    paragraphs_failed: AtomicUsize,
+    /// Paragraphs of the whole book and how many were synthesized, for the ETA
+    book_paragraphs: AtomicUsize,
+    book_paragraphs_done: AtomicUsize,
+    started: Mutex<Instant>,
+    events: Option<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl Default for Progress {
+    fn default() -> Progress {
+        Progress::new(None)
+    }
+}
+
+impl Progress {
+    pub fn new(events: Option<Box<dyn Write + Send>>) -> Progress {
+        Progress {
+            stage: Mutex::new((String::new(), Instant::now())),
+            chapter: AtomicUsize::new(0),
+            chapters: AtomicUsize::new(0),
+            paragraphs: AtomicUsize::new(0),
+            paragraphs_done: AtomicUsize::new(0),
+            paragraphs_failed: AtomicUsize::new(0),
+            book_paragraphs: AtomicUsize::new(0),
+            book_paragraphs_done: AtomicUsize::new(0),
+            started: Mutex::new(Instant::now()),
+            events: events.map(Mutex::new),
+        }
+    }
+
+    /// Whether events are written, in which case the terminal progress bar is left out
+    pub fn is_json(&self) -> bool {
+        self.events.is_some()
+    }
+
+    /// A book with this many chapters and paragraphs starts
+    pub fn start_book(&self, title: &str, chapters: usize, paragraphs: usize) {
+        if let Ok(mut started) = self.started.lock() {
+            *started = Instant::now();
+        }
+        self.chapters.store(chapters, Ordering::Relaxed);
+        self.book_paragraphs.store(paragraphs, Ordering::Relaxed);
+        self.book_paragraphs_done.store(0, Ordering::Relaxed);
+        self.emit(serde_json::json!({
+            "event": "book_started",
+            "title": title,
+            "chapters": chapters,
+            "paragraphs": paragraphs,
+        }));
+    }
+
+    /// Ends the current stage, if any, and starts the next one (none for "")
+    pub fn set_stage(&self, stage: &str) {
+        let previous = match self.stage.lock() {
+            Ok(mut current) => {
+                std::mem::replace(&mut *current, (stage.to_string(), Instant::now()))
+            }
+            Err(_) => return,
+        };
+        let (previous, since) = previous;
+        if previous == stage {
+            return;
+        }
+        if !previous.is_empty() {
+            self.emit(serde_json::json!({
+                "event": "stage_finished",
+                "stage": previous,
+                "seconds": since.elapsed().as_secs_f64(),
+            }));
+        }
+        if stage.is_empty() {
+            return;
+        }
+        self.emit(serde_json::json!({
+            "event": "stage_started",
+            "stage": stage,
+            "chapter": self.chapter.load(Ordering::Relaxed),
+        }));
+    }
+
+    /// A new chapter (1-based) starts; the paragraph counters restart with it
+    pub fn start_chapter(&self, chapter: usize, title: &str, paragraphs: usize) {
+        self.chapter.store(chapter, Ordering::Relaxed);
+        self.paragraphs.store(paragraphs, Ordering::Relaxed);
+        self.paragraphs_done.store(0, Ordering::Relaxed);
+        self.paragraphs_failed.store(0, Ordering::Relaxed);
+        self.emit(serde_json::json!({
+            "event": "chapter_started",
+            "chapter": chapter,
+            "chapters": self.chapters.load(Ordering::Relaxed),
+            "title": title,
+            "paragraphs": paragraphs,
+        }));
+    }
+
+    pub fn paragraph_done(&self, paragraph: usize) {
+        self.paragraphs_done.fetch_add(1, Ordering::Relaxed);
+        let done = self.book_paragraphs_done.fetch_add(1, Ordering::Relaxed) + 1;
+        if !self.is_json() {
+            return;
+        }
+        let total = self.book_paragraphs.load(Ordering::Relaxed);
+        self.emit(serde_json::json!({
+            "event": "paragraph_done",
+            "chapter": self.chapter.load(Ordering::Relaxed),
+            "paragraph": paragraph,
+            "done": done,
+            "total": total,
+            "eta_seconds": self.eta(done, total),
+        }));
+    }
+
+    pub fn paragraph_failed(&self, paragraph: usize, error: &str) {
+        self.paragraphs_failed.fetch_add(1, Ordering::Relaxed);
+        self.book_paragraphs_done.fetch_add(1, Ordering::Relaxed);
+        self.emit(serde_json::json!({
+            "event": "paragraph_failed",
+            "chapter": self.chapter.load(Ordering::Relaxed),
+            "paragraph": paragraph,
+            "error": error,
+        }));
+    }
+
+    /// The chapter file is made; `duration` in ms
+    pub fn chapter_finished(&self, chapter: usize, title: &str, duration: f64) {
+        self.emit(serde_json::json!({
+            "event": "chapter_finished",
+            "chapter": chapter,
+            "title": title,
+            "duration_ms": duration.round(),
+        }));
+    }
+
+    pub fn output(&self, path: &str) {
+        self.emit(serde_json::json!({ "event": "output", "path": path }));
+    }
+
+    /// The book is done, or failed
+    pub fn finished(&self, result: &Result<Vec<PathBuf>, String>) {
+        self.set_stage("");
+        let seconds = self
+            .started
+            .lock()
+            .map(|started| started.elapsed().as_secs_f64())
+            .unwrap_or(0.0);
+        match result {
+            Ok(outputs) => self.emit(serde_json::json!({
+                "event": "book_finished",
+                "outputs": outputs.iter().map(|path| path.to_string_lossy()).collect::<Vec<_>>(),
+                "seconds": seconds,
+            })),
+            Err(e) => self.emit(serde_json::json!({
+                "event": "book_failed",
+                "error": e,
+                "seconds": seconds,
+            })),
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let stage = self
+            .stage
+            .lock()
+            .map(|stage| stage.0.clone())
+            .unwrap_or_default();
+        let done = self.book_paragraphs_done.load(Ordering::Relaxed);
+        let total = self.book_paragraphs.load(Ordering::Relaxed);
+        serde_json::json!({
+            "stage": stage,
+            "chapter": self.chapter.load(Ordering::Relaxed),
+            "chapters": self.chapters.load(Ordering::Relaxed),
+            "paragraphs": self.paragraphs.load(Ordering::Relaxed),
+            "paragraphs_done": self.paragraphs_done.load(Ordering::Relaxed),
+            "paragraphs_failed": self.paragraphs_failed.load(Ordering::Relaxed),
+            "book_paragraphs": total,
+            "book_paragraphs_done": done,
+            "eta_seconds": self.eta(done, total),
+        })
+    }
+
+    // Remaining paragraphs at the pace so far
+    fn eta(&self, done: usize, total: usize) -> Option<f64> {
+        if done == 0 || done > total {
+            return None;
+        }
+        let elapsed = self.started.lock().ok()?.elapsed().as_secs_f64();
+        Some((elapsed / done as f64 * (total - done) as f64).round())
+    }
+
+    fn emit(&self, mut event: serde_json::Value) {
+        let Some(events) = &self.events else {
+            return;
+        };
+        let seconds = self
+            .started
+            .lock()
+            .map(|started| started.elapsed().as_secs_f64())
+            .unwrap_or(0.0);
+        event["time_ms"] = (seconds * 1000.0).round().into();
+        if let Ok(mut events) = events.lock() {
+            // A consumer that went away shouldn't stop the conversion
+            writeln!(events, "{}", event).ok();
+            events.flush().ok();
+        }
+    }
+}
@@ -0,0 +1,67 @@
+// src/sample.rs
+use crate::book::Book;
+use crate::estimate;
+use crate::naming;
+use crate::tts::Voice;
+use std::path::{Path, PathBuf};
+
+/// Text of the sample: the given paragraph of the chapter (both from 1), or as much of
+/// the chapter as the voice reads in `seconds`, cut after a whole word
+pub fn sample_text(
+    book: &Book,
+    chapter: usize,
+    paragraph: Option<usize>,
+    seconds: f64,
+    voice: &Voice,
+) -> Result<String, String> {
+    let chapters = book.get_all_chapters();
+    let Some((_, content)) = chapter.checked_sub(1).and_then(|i| chapters.get(i)) else {
+        return Err(format!(
+            "Chapter {} doesn't exist, the book has {} chapters",
+            chapter,
+            chapters.len()
+        ));
+    };
+    if let Some(paragraph) = paragraph {
+        return match paragraph.checked_sub(1).and_then(|i| content.get(i)) {
+            Some(text) => Ok(text.clone()),
+            None => Err(format!(
+                "Paragraph {} doesn't exist, chapter {} has {} paragraphs",
+                paragraph,
+                chapter,
+                content.len()
+            )),
+        };
+    }
+
+    let mut taken: Vec<String> = Vec::new();
+    let mut left = seconds;
+    for text in content.iter() {
+        let length = estimate::speaking_seconds(text, voice);
+        if length <= left {
+            taken.push(text.clone());
+            left -= length;
+            continue;
+        }
+        // The paragraph that doesn't fit is cut short
+        let mut words = Vec::new();
+        for word in text.split_whitespace() {
+            words.push(word);
+            if estimate::speaking_seconds(&words.join(" "), voice) >= left {
+                break;
+            }
+        }
+        taken.push(words.join(" "));
+        break;
+    }
+    if taken.iter().all(|text| text.trim().is_empty()) {
+        return Err(format!("Chapter {} has no text", chapter));
+    }
+    Ok(taken.join("\n"))
+}
+
+/// "01 en-US-BrianNeural medium.mp3", numbered so the clips sort in the order asked for
+pub fn clip_path(dir: &Path, number: usize, voice: &Voice, extension: &str) -> PathBuf {
+    let name = naming::sanitize(&format!("{:02} {} {}", number, voice.name, voice.rate));
+    dir.join(format!("{}.{}", name, extension))
+}
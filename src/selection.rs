@@ -0,0 +1,167 @@
+// src/selection.rs
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Chapters picked with --chapters, numbered from 1 and sorted
+#[derive(Clone, Debug)]
+pub struct Selection(pub Vec<usize>);
+
+impl Selection {
+    pub fn contains(&self, chapter: usize) -> bool {
+        self.0.contains(&chapter)
+    }
+}
+
+/// Parses a list of chapters such as "3,5-7"
+pub fn parse_chapters(spec: &str) -> Result<Selection, String> {
+    let number = |text: &str| {
+        text.trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|number| *number > 0)
+            .ok_or_else(|| format!("Invalid chapter number \"{}\"", text.trim()))
+    };
+    let mut chapters = Vec::new();
+    for part in spec.split(',').filter(|part| !part.trim().is_empty()) {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (number(first)?, number(last)?);
+                if first > last {
+                    return Err(format!("Invalid chapter range \"{}\"", part.trim()));
+                }
+                chapters.extend(first..=last);
+            }
+            None => chapters.push(number(part)?),
+        }
+    }
+    if chapters.is_empty() {
+        return Err("No chapters given".to_string());
+    }
+    chapters.sort();
+    chapters.dedup();
+    Ok(Selection(chapters))
+}
+
+/// The chapters written back as a short list, e.g. "3, 5-7"
+pub fn describe(selection: &Selection) -> String {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for &chapter in &selection.0 {
+        match ranges.last_mut() {
+            Some((_, last)) if *last + 1 == chapter => *last = chapter,
+            _ => ranges.push((chapter, chapter)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|&(first, last)| {
+            if first == last {
+                first.to_string()
+            } else {
+                format!("{}-{}", first, last)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Checks that every selected chapter is in a book of `total` chapters
+pub fn check_range(selection: &Selection, total: usize) -> Result<(), String> {
+    match selection.0.iter().find(|chapter| **chapter > total) {
+        Some(chapter) => Err(format!(
+            "Chapter {} was selected, but the book has {} chapters",
+            chapter, total
+        )),
+        None => Ok(()),
+    }
+}
+
+/// The chapter audio of an earlier `--output-format chapters` run ("01 - Title.m4a",
+/// ...) by chapter number. Chapters too short to be read have no file.
+pub fn chapter_set(
+    dir: &Path,
+    extension: &str,
+    total: usize,
+) -> Result<BTreeMap<usize, PathBuf>, String> {
+    let entries =
+        fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+    let files: BTreeMap<usize, PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == extension))
+        .filter_map(|path| {
+            let name = path.file_name()?.to_string_lossy().to_string();
+            let number = name.split(" - ").next()?.parse::<usize>().ok()?;
+            Some((number, path))
+        })
+        .collect();
+
+    if files.is_empty() {
+        return Err(format!(
+            "{} has no chapter files (.{})",
+            dir.display(),
+            extension
+        ));
+    }
+    if let Some(number) = files
+        .keys()
+        .find(|number| **number == 0 || **number > total)
+    {
+        return Err(format!(
+            "{} has a file for chapter {}, the book has {} chapters",
+            dir.display(),
+            number,
+            total
+        ));
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chapters_and_ranges_are_parsed_sorted() {
+        assert_eq!(parse_chapters("3,5-7").unwrap().0, [3, 5, 6, 7]);
+        assert_eq!(parse_chapters(" 9 , 2 - 3,3,,").unwrap().0, [2, 3, 9]);
+        assert_eq!(parse_chapters("4-4").unwrap().0, [4]);
+
+        for spec in ["", " , ", "0", "2-", "-2", "7-5", "one", "1.5"] {
+            assert!(parse_chapters(spec).is_err(), "{:?} was accepted", spec);
+        }
+    }
+
+    #[test]
+    fn selection_is_described_as_ranges() {
+        let selection = parse_chapters("1,3,4,5,8,9").unwrap();
+        assert_eq!(describe(&selection), "1, 3-5, 8-9");
+        assert!(selection.contains(4));
+        assert!(!selection.contains(2));
+
+        assert!(check_range(&selection, 9).is_ok());
+        let error = check_range(&selection, 8).unwrap_err();
+        assert_eq!(error, "Chapter 9 was selected, but the book has 8 chapters");
+    }
+
+    #[test]
+    fn chapter_set_is_read_by_number() {
+        let dir = std::env::temp_dir().join(format!("edgeab-selection-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in [
+            "01 - One.m4a",
+            "03 - Three - Part B.m4a",
+            "cover.jpg",
+            "notes.m4a",
+        ] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let set = chapter_set(&dir, "m4a", 3).unwrap();
+        assert_eq!(set.keys().copied().collect::<Vec<_>>(), [1, 3]);
+        assert_eq!(set[&3], dir.join("03 - Three - Part B.m4a"));
+        assert!(chapter_set(&dir, "m4a", 2).is_err());
+        assert!(chapter_set(&dir, "mp3", 3).is_err());
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,692 @@
+// src/server.rs
+use crate::batch;
+use crate::output::OutputFormat;
+use crate::progress::{say, Progress};
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tiny_http::{Header, Method, Request, Response, ResponseBox, Server};
+use tokio::sync::Notify;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+impl JobStatus {
+    fn name(&self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Done => "done",
+            JobStatus::Failed => "failed",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<JobStatus> {
+        [
+            JobStatus::Queued,
+            JobStatus::Running,
+            JobStatus::Done,
+            JobStatus::Failed,
+        ]
+        .into_iter()
+        .find(|status| status.name() == name)
+    }
+}
+
+/// A book uploaded for conversion, with the options chosen for it
+#[derive(Clone, Debug)]
+pub struct Job {
+    pub id: String,
+    pub status: JobStatus,
+    /// Name of the uploaded file in the job folder
+    pub file: String,
+    pub voice: Option<String>,
+    pub rate: Option<String>,
+    pub output_format: Option<OutputFormat>,
+    pub metadata: Vec<(String, String)>,
+    pub error: Option<String>,
+    /// Files written, relative to the job's output folder
+    pub outputs: Vec<String>,
+    /// Seconds since the epoch
+    pub created: u64,
+}
+
+impl Job {
+    fn to_json(&self) -> serde_json::Value {
+        let metadata: serde_json::Map<String, serde_json::Value> = self
+            .metadata
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone().into()))
+            .collect();
+        let output_format = self
+            .output_format
+            .and_then(|format| format.to_possible_value())
+            .map(|value| value.get_name().to_string());
+        serde_json::json!({
+            "id": self.id,
+            "status": self.status.name(),
+            "file": self.file,
+            "voice": self.voice,
+            "rate": self.rate,
+            "output_format": output_format,
+            "metadata": metadata,
+            "error": self.error,
+            "outputs": self.outputs,
+            "created": self.created,
+        })
+    }
+
+    fn from_json(json: &serde_json::Value) -> Option<Job> {
+        let text = |key: &str| json[key].as_str().map(|value| value.to_string());
+        Some(Job {
+            id: text("id")?,
+            status: JobStatus::from_name(json["status"].as_str()?)?,
+            file: text("file")?,
+            voice: text("voice"),
+            rate: text("rate"),
+            output_format: json["output_format"]
+                .as_str()
+                .and_then(|name| OutputFormat::from_str(name, true).ok()),
+            metadata: json["metadata"]
+                .as_object()
+                .map(|fields| {
+                    fields
+                        .iter()
+                        .filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_string())))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            error: text("error"),
+            outputs: json["outputs"]
+                .as_array()
+                .map(|outputs| {
+                    outputs
+                        .iter()
+                        .filter_map(|output| output.as_str().map(|output| output.to_string()))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            created: json["created"].as_u64().unwrap_or(0),
+        })
+    }
+}
+
+/// The jobs, each kept in `<dir>/<id>/` with its job.json, the upload and an `out` folder
+pub struct JobStore {
+    dir: PathBuf,
+    jobs: BTreeMap<String, Job>,
+}
+
+impl JobStore {
+    /// Loads the jobs of earlier runs. A job that was running when the server stopped
+    /// is queued again.
+    pub fn load(dir: &Path) -> io::Result<JobStore> {
+        fs::create_dir_all(dir)?;
+        let mut jobs = BTreeMap::new();
+        for entry in fs::read_dir(dir)? {
+            let job_file = entry?.path().join("job.json");
+            let Ok(text) = fs::read_to_string(&job_file) else {
+                continue;
+            };
+            let json: serde_json::Value = match serde_json::from_str(&text) {
+                Ok(json) => json,
+                Err(_) => continue,
+            };
+            if let Some(mut job) = Job::from_json(&json) {
+                if job.status == JobStatus::Running {
+                    job.status = JobStatus::Queued;
+                }
+                jobs.insert(job.id.clone(), job);
+            }
+        }
+        let store = JobStore {
+            dir: dir.to_path_buf(),
+            jobs,
+        };
+        for job in store.jobs.values() {
+            store.save(job)?;
+        }
+        Ok(store)
+    }
+
+    pub fn save(&self, job: &Job) -> io::Result<()> {
+        let job_dir = self.dir.join(&job.id);
+        fs::create_dir_all(&job_dir)?;
+        let text = serde_json::to_string_pretty(&job.to_json()).map_err(io::Error::other)?;
+        fs::write(job_dir.join("job.json"), text)
+    }
+
+    pub fn input_path(&self, job: &Job) -> PathBuf {
+        self.dir.join(&job.id).join(&job.file)
+    }
+
+    pub fn output_dir(&self, id: &str) -> PathBuf {
+        self.dir.join(id).join("out")
+    }
+
+    /// The oldest job still waiting
+    pub fn next_queued(&self) -> Option<Job> {
+        self.jobs
+            .values()
+            .filter(|job| job.status == JobStatus::Queued)
+            .min_by_key(|job| (job.created, job.id.clone()))
+            .cloned()
+    }
+
+    pub fn update(&mut self, job: Job) -> io::Result<()> {
+        self.save(&job)?;
+        self.jobs.insert(job.id.clone(), job);
+        Ok(())
+    }
+
+    fn add(&mut self, job: Job, upload: &[u8]) -> io::Result<()> {
+        fs::create_dir_all(self.dir.join(&job.id))?;
+        fs::write(self.input_path(&job), upload)?;
+        self.update(job)
+    }
+}
+
+/// What the HTTP side and the worker share
+pub struct ServerState {
+    pub store: Mutex<JobStore>,
+    /// The job being converted and its counters
+    pub running: Mutex<Option<(String, Arc<Progress>)>>,
+    /// Woken when a job is added
+    pub wake: Notify,
+}
+
+impl ServerState {
+    pub fn new(store: JobStore) -> ServerState {
+        ServerState {
+            store: Mutex::new(store),
+            running: Mutex::new(None),
+            wake: Notify::new(),
+        }
+    }
+}
+
+/// Answers requests until the server fails. Blocking, so it runs on its own thread.
+///
+/// - `POST /jobs?name=book.epub&voice=..&rate=..&format=..&meta=title=..` with the
+///   book as the body queues a job
+/// - `GET /jobs` lists the jobs, `GET /jobs/<id>` shows one with its progress
+/// - `GET /jobs/<id>/result` downloads the audiobook, `GET /jobs/<id>/files/<path>`
+///   any other file the job wrote
+pub fn serve_http(address: &str, state: Arc<ServerState>) -> Result<(), String> {
+    let server =
+        Server::http(address).map_err(|e| format!("Failed to listen on {}: {}", address, e))?;
+    for request in server.incoming_requests() {
+        handle(request, &state);
+    }
+    Ok(())
+}
+
+fn handle(mut request: Request, state: &ServerState) {
+    let url = request.url().to_string();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let segments: Vec<String> = path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(percent_decode)
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    let method = request.method().clone();
+
+    let result = match (&method, segments.as_slice()) {
+        (Method::Get, []) | (Method::Get, ["jobs"]) => list_jobs(state),
+        (Method::Post, ["jobs"]) => add_job(&mut request, query, state),
+        (Method::Get, ["jobs", id]) => show_job(id, state),
+        (Method::Get, ["jobs", id, "result"]) => download(id, None, state),
+        (Method::Get, ["jobs", id, "files", rest @ ..]) => {
+            download(id, Some(rest.join("/")), state)
+        }
+        _ => Err((404, "Not found".to_string())),
+    };
+
+    let response = match result {
+        Ok(response) => response,
+        Err((status, message)) => json_response(status, &serde_json::json!({ "error": message })),
+    };
+    if let Err(e) = request.respond(response) {
+        say!("Failed to answer {}: {}", url, e);
+    }
+}
+
+type Reply = Result<ResponseBox, (u16, String)>;
+
+fn list_jobs(state: &ServerState) -> Reply {
+    let store = state.store.lock().map_err(|_| internal())?;
+    let jobs: Vec<serde_json::Value> = store.jobs.values().map(Job::to_json).collect();
+    Ok(json_response(200, &serde_json::json!({ "jobs": jobs })))
+}
+
+fn show_job(id: &str, state: &ServerState) -> Reply {
+    let store = state.store.lock().map_err(|_| internal())?;
+    let job = store.jobs.get(id).ok_or((404, format!("No job {}", id)))?;
+    let mut json = job.to_json();
+    if let Ok(running) = state.running.lock() {
+        if let Some((_, progress)) = running.as_ref().filter(|(running, _)| running == id) {
+            json["progress"] = progress.to_json();
+        }
+    }
+    Ok(json_response(200, &json))
+}
+
+fn add_job(request: &mut Request, query: &str, state: &ServerState) -> Reply {
+    let mut name = None;
+    let mut job = Job {
+        id: new_id(),
+        status: JobStatus::Queued,
+        file: String::new(),
+        voice: None,
+        rate: None,
+        output_format: None,
+        metadata: Vec::new(),
+        error: None,
+        outputs: Vec::new(),
+        created: now(),
+    };
+    for (key, value) in parse_query(query) {
+        match key.as_str() {
+            "name" => name = Some(value),
+            "voice" => job.voice = Some(value),
+            "rate" => job.rate = Some(value),
+            "format" | "output_format" => {
+                let format = OutputFormat::from_str(&value, true)
+                    .map_err(|_| (400, format!("Unknown output format {}", value)))?;
+                job.output_format = Some(format);
+            }
+            "meta" => {
+                let (field, value) = value
+                    .split_once('=')
+                    .ok_or((400, format!("Expected meta=field=value, got {}", value)))?;
+                job.metadata.push((field.to_string(), value.to_string()));
+            }
+            _ => return Err((400, format!("Unknown parameter {}", key))),
+        }
+    }
+
+    // Only the file name is kept, the upload always lands in the job folder
+    let name = name.ok_or((400, "The name parameter is required".to_string()))?;
+    let name = Path::new(&name)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .filter(|name| !name.starts_with('.'))
+        .ok_or((400, format!("Invalid file name {}", name)))?;
+    if !batch::is_book(Path::new(&name)) {
+        return Err((400, format!("{} is not a supported book format", name)));
+    }
+    job.file = name;
+
+    let mut upload = Vec::new();
+    request
+        .as_reader()
+        .read_to_end(&mut upload)
+        .map_err(|e| (400, format!("Failed to read the upload: {}", e)))?;
+    if upload.is_empty() {
+        return Err((400, "The request has no book in its body".to_string()));
+    }
+
+    let json = job.to_json();
+    state
+        .store
+        .lock()
+        .map_err(|_| internal())?
+        .add(job, &upload)
+        .map_err(|e| (500, format!("Failed to save the job: {}", e)))?;
+    state.wake.notify_one();
+    Ok(json_response(201, &json))
+}
+
+// Without a path, the first audio file the job wrote. The file is streamed after the
+// store is unlocked, an audiobook takes a while to send.
+fn download(id: &str, file: Option<String>, state: &ServerState) -> Reply {
+    let store = state.store.lock().map_err(|_| internal())?;
+    let job = store.jobs.get(id).ok_or((404, format!("No job {}", id)))?;
+    if job.status != JobStatus::Done {
+        return Err((409, format!("Job {} is {}", id, job.status.name())));
+    }
+    let file = match file {
+        Some(file) => job
+            .outputs
+            .iter()
+            .find(|output| **output == file)
+            .ok_or((404, format!("Job {} has no file {}", id, file)))?,
+        None => job
+            .outputs
+            .iter()
+            .find(|output| is_audio(output))
+            .or(job.outputs.first())
+            .ok_or((404, format!("Job {} wrote no files", id)))?,
+    }
+    .clone();
+    let path = store.output_dir(id).join(&file);
+    drop(store);
+    let opened = File::open(&path).map_err(|e| (500, format!("Failed to read {}: {}", file, e)))?;
+
+    let name = Path::new(&file)
+        .file_name()
+        .map(|name| name.to_string_lossy().replace('"', "'"))
+        .unwrap_or_default();
+    let mut response = Response::from_file(opened).with_status_code(200);
+    response.add_header(header("Content-Type", content_type(&file)));
+    response.add_header(header(
+        "Content-Disposition",
+        &format!("attachment; filename=\"{}\"", name),
+    ));
+    Ok(response.boxed())
+}
+
+/// Every file below `dir`, relative to it with '/' separators, audio files first
+pub fn list_outputs(dir: &Path) -> Vec<String> {
+    fn walk(dir: &Path, prefix: &str, files: &mut Vec<String>) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let relative = if prefix.is_empty() {
+                name
+            } else {
+                format!("{}/{}", prefix, name)
+            };
+            if entry.path().is_dir() {
+                walk(&entry.path(), &relative, files);
+            } else {
+                files.push(relative);
+            }
+        }
+    }
+    let mut files = Vec::new();
+    walk(dir, "", &mut files);
+    files.sort_by_key(|file| (!is_audio(file), file.clone()));
+    files
+}
+
+fn is_audio(file: &str) -> bool {
+    content_type(file).starts_with("audio/")
+}
+
+fn content_type(file: &str) -> &'static str {
+    let extension = Path::new(file)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "m4b" | "m4a" => "audio/mp4",
+        "mp3" => "audio/mpeg",
+        "opus" | "ogg" => "audio/ogg",
+        "epub" => "application/epub+zip",
+        "json" => "application/json",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "vtt" => "text/vtt",
+        "srt" | "lrc" | "txt" | "abs" => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+fn json_response(status: u16, json: &serde_json::Value) -> ResponseBox {
+    let mut response = Response::from_string(json.to_string()).with_status_code(status);
+    response.add_header(header("Content-Type", "application/json"));
+    response.boxed()
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("valid header")
+}
+
+fn internal() -> (u16, String) {
+    (500, "The job store is unavailable".to_string())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+}
+
+// Increasing with time, so jobs list in the order they came in
+fn new_id() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_nanos())
+        .unwrap_or(0);
+    format!("{:x}", nanos)
+}
+
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+// %XX escapes and '+' for a space
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+                match u8::from_str_radix(hex, 16) {
+                    Ok(byte) => {
+                        decoded.push(byte);
+                        i += 3;
+                        continue;
+                    }
+                    Err(_) => decoded.push(b'%'),
+                }
+            }
+            b'+' => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    // An empty folder of its own in the system temp folder
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("edgeab-{}-{}", name, std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // Answers requests on a free port the way serve_http does, returns the address
+    fn start(state: Arc<ServerState>) -> String {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let address = server.server_addr().to_ip().unwrap().to_string();
+        std::thread::spawn(move || {
+            for request in server.incoming_requests() {
+                handle(request, &state);
+            }
+        });
+        address
+    }
+
+    // The status and body of the answer
+    fn request(address: &str, method: &str, path: &str, body: &[u8]) -> (u16, Vec<u8>) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            method,
+            path,
+            address,
+            body.len()
+        )
+        .unwrap();
+        stream.write_all(body).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let end = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .unwrap();
+        let head = String::from_utf8_lossy(&response[..end]).to_string();
+        let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+        (status, response[end + 4..].to_vec())
+    }
+
+    fn json(body: &[u8]) -> serde_json::Value {
+        serde_json::from_slice(body).unwrap()
+    }
+
+    #[test]
+    fn percent_decode_handles_escapes_and_plus() {
+        assert_eq!(percent_decode("a%20b+c"), "a b c");
+        assert_eq!(percent_decode("%E2%9C%93"), "\u{2713}");
+        assert_eq!(percent_decode("title%3DHello"), "title=Hello");
+        // Broken escapes are kept as they are
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz"), "%zz");
+    }
+
+    #[test]
+    fn load_queues_running_jobs_again() {
+        let dir = temp_dir("store");
+        let mut store = JobStore::load(&dir).unwrap();
+        let job = Job {
+            id: "1".to_string(),
+            status: JobStatus::Running,
+            file: "book.txt".to_string(),
+            voice: Some("en-US-AvaNeural".to_string()),
+            rate: None,
+            output_format: Some(OutputFormat::Mp3),
+            metadata: vec![("title".to_string(), "Hello".to_string())],
+            error: None,
+            outputs: Vec::new(),
+            created: 1,
+        };
+        store.add(job, b"# Chapter\nText\n").unwrap();
+        // A folder without a readable job.json is left alone
+        fs::create_dir_all(dir.join("broken")).unwrap();
+        fs::write(dir.join("broken").join("job.json"), "{").unwrap();
+
+        let store = JobStore::load(&dir).unwrap();
+        assert_eq!(store.jobs.len(), 1);
+        let job = store.next_queued().unwrap();
+        assert_eq!(job.id, "1");
+        assert_eq!(job.voice.as_deref(), Some("en-US-AvaNeural"));
+        assert_eq!(job.output_format, Some(OutputFormat::Mp3));
+        assert_eq!(
+            job.metadata,
+            vec![("title".to_string(), "Hello".to_string())]
+        );
+        let saved = fs::read_to_string(dir.join("1").join("job.json")).unwrap();
+        assert_eq!(json(saved.as_bytes())["status"], "queued");
+        assert_eq!(
+            fs::read(store.input_path(&job)).unwrap(),
+            b"# Chapter\nText\n"
+        );
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn jobs_are_queued_listed_and_downloaded() {
+        let dir = temp_dir("api");
+        let state = Arc::new(ServerState::new(JobStore::load(&dir).unwrap()));
+        let address = start(state.clone());
+
+        let upload = b"# Chapter One\nFirst paragraph.\nSecond paragraph.\n";
+        let (status, body) = request(
+            &address,
+            "POST",
+            "/jobs?name=My%20Book.txt&voice=en-GB-RyanNeural&format=mp3&meta=title%3DHello",
+            upload,
+        );
+        assert_eq!(status, 201);
+        let created = json(&body);
+        let id = created["id"].as_str().unwrap().to_string();
+        assert_eq!(created["file"], "My Book.txt");
+        assert_eq!(created["output_format"], "mp3");
+        assert_eq!(created["metadata"]["title"], "Hello");
+
+        let (status, body) = request(&address, "GET", "/jobs", b"");
+        assert_eq!(status, 200);
+        assert_eq!(json(&body)["jobs"][0]["id"], id.as_str());
+        let (status, body) = request(&address, "GET", &format!("/jobs/{}", id), b"");
+        assert_eq!(status, 200);
+        assert_eq!(json(&body)["status"], "queued");
+        let (status, _) = request(&address, "GET", &format!("/jobs/{}/result", id), b"");
+        assert_eq!(status, 409);
+
+        // What the worker does when the job is converted
+        let job = {
+            let store = state.store.lock().unwrap();
+            let job = store.next_queued().unwrap();
+            assert_eq!(fs::read(store.input_path(&job)).unwrap(), upload);
+            let output_dir = store.output_dir(&id);
+            fs::create_dir_all(output_dir.join("Hello")).unwrap();
+            fs::write(output_dir.join("Hello").join("Hello.mp3"), b"audio").unwrap();
+            fs::write(output_dir.join("Hello").join("desc.txt"), b"text").unwrap();
+            Job {
+                status: JobStatus::Done,
+                outputs: list_outputs(&output_dir),
+                ..job
+            }
+        };
+        assert_eq!(job.outputs, ["Hello/Hello.mp3", "Hello/desc.txt"]);
+        state.store.lock().unwrap().update(job).unwrap();
+
+        let (status, body) = request(&address, "GET", &format!("/jobs/{}/result", id), b"");
+        assert_eq!(status, 200);
+        assert_eq!(body, b"audio");
+        let path = format!("/jobs/{}/files/Hello/desc.txt", id);
+        let (status, body) = request(&address, "GET", &path, b"");
+        assert_eq!(status, 200);
+        assert_eq!(body, b"text");
+        let path = format!("/jobs/{}/files/Hello/other.txt", id);
+        assert_eq!(request(&address, "GET", &path, b"").0, 404);
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn bad_requests_are_refused() {
+        let dir = temp_dir("refused");
+        let state = Arc::new(ServerState::new(JobStore::load(&dir).unwrap()));
+        let address = start(state.clone());
+
+        let refused = [
+            ("POST", "/jobs?voice=x", &b"text"[..]),
+            ("POST", "/jobs?name=notes.xyz", b"text"),
+            ("POST", "/jobs?name=book.txt&format=wma", b"text"),
+            ("POST", "/jobs?name=book.txt&colour=red", b"text"),
+            ("POST", "/jobs?name=book.txt", b""),
+            ("POST", "/jobs?name=.hidden.txt", b"text"),
+        ];
+        for (method, path, body) in refused {
+            let (status, body) = request(&address, method, path, body);
+            assert_eq!(status, 400, "{} {}", method, path);
+            assert!(json(&body)["error"].is_string());
+        }
+        assert_eq!(request(&address, "GET", "/jobs/nope", b"").0, 404);
+        assert_eq!(request(&address, "DELETE", "/jobs", b"").0, 404);
+        assert!(state.store.lock().unwrap().jobs.is_empty());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
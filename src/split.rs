@@ -0,0 +1,165 @@
+// src/split.rs
+use std::ops::Range;
+
+/// Limits for splitting a long book into several parts at chapter boundaries
+#[derive(Clone, Debug, Default)]
+pub struct SplitLimits {
+    /// Longest allowed part in milliseconds, the unit `get_audio_length` reports
+    pub max_duration: Option<f64>,
+    /// Largest allowed part in bytes
+    pub max_size: Option<u64>,
+}
+
+/// Groups consecutive chapters into parts that stay under the limits.
+/// A single chapter that is over a limit on its own still gets a part of its own.
+pub fn plan_parts(
+    chapter_lengths: &[f64],
+    chapter_sizes: &[u64],
+    limits: &SplitLimits,
+) -> Vec<Range<usize>> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut duration = 0.0;
+    let mut size = 0;
+
+    for (i, &length) in chapter_lengths.iter().enumerate() {
+        let chapter_size = chapter_sizes.get(i).copied().unwrap_or(0);
+
+        let too_long = limits
+            .max_duration
+            .is_some_and(|max| duration + length > max);
+        let too_big = limits.max_size.is_some_and(|max| size + chapter_size > max);
+        if i > start && (too_long || too_big) {
+            parts.push(start..i);
+            start = i;
+            duration = 0.0;
+            size = 0;
+        }
+
+        duration += length;
+        size += chapter_size;
+    }
+
+    if start < chapter_lengths.len() {
+        parts.push(start..chapter_lengths.len());
+    }
+    parts
+}
+
+/// Parses durations like "10h", "90m", "3600s", "12:30:00" or plain seconds into milliseconds
+pub fn parse_duration(value: &str) -> Result<f64, String> {
+    let value = value.trim();
+    let invalid = || {
+        format!(
+            "Invalid duration '{}', use e.g. 10h, 90m or 12:30:00",
+            value
+        )
+    };
+
+    let seconds = if value.contains(':') {
+        let mut seconds = 0.0;
+        for part in value.split(':') {
+            let part = part.parse::<f64>().map_err(|_| invalid())?;
+            if part < 0.0 {
+                return Err(invalid());
+            }
+            seconds = seconds * 60.0 + part;
+        }
+        seconds
+    } else {
+        let (number, multiplier) = match value.chars().last() {
+            Some('h') => (&value[..value.len() - 1], 3600.0),
+            Some('m') => (&value[..value.len() - 1], 60.0),
+            Some('s') => (&value[..value.len() - 1], 1.0),
+            _ => (value, 1.0),
+        };
+        number.parse::<f64>().map_err(|_| invalid())? * multiplier
+    };
+    // "inf" and "NaN" parse as numbers too
+    if !seconds.is_finite() || seconds <= 0.0 {
+        return Err(invalid());
+    }
+    Ok(seconds * 1000.0)
+}
+
+/// Parses sizes like "4G", "700M", "500K" or plain bytes
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let invalid = || format!("Invalid size '{}', use e.g. 4G or 700M", value);
+
+    let upper = value.to_uppercase();
+    let upper = upper.trim_end_matches('B');
+    let (number, multiplier) = match upper.chars().last() {
+        Some('G') => (&upper[..upper.len() - 1], 1024 * 1024 * 1024),
+        Some('M') => (&upper[..upper.len() - 1], 1024 * 1024),
+        Some('K') => (&upper[..upper.len() - 1], 1024),
+        _ => (upper, 1),
+    };
+    let size = number.parse::<f64>().map_err(|_| invalid())? * multiplier as f64;
+    // Anything under a byte would be cut to 0
+    if !size.is_finite() || size < 1.0 {
+        return Err(invalid());
+    }
+    Ok(size as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_takes_units_and_clock_times() {
+        assert_eq!(parse_duration("10h"), Ok(36_000_000.0));
+        assert_eq!(parse_duration("90m"), Ok(5_400_000.0));
+        assert_eq!(parse_duration(" 1.5s "), Ok(1_500.0));
+        assert_eq!(parse_duration("3600"), Ok(3_600_000.0));
+        assert_eq!(parse_duration("12:30:00"), Ok(45_000_000.0));
+        assert_eq!(parse_duration("1:30"), Ok(90_000.0));
+    }
+
+    #[test]
+    fn parse_duration_rejects_what_is_not_a_length() {
+        for value in [
+            "", "h", "0", "0:00:00", "-5m", "1:-30", "1::2", "ten", "inf", "NaN", "infh", "1:inf",
+        ] {
+            assert!(parse_duration(value).is_err(), "{} was accepted", value);
+        }
+    }
+
+    #[test]
+    fn parse_size_takes_units() {
+        assert_eq!(parse_size("4G"), Ok(4 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("700mb"), Ok(700 * 1024 * 1024));
+        assert_eq!(parse_size("1.5K"), Ok(1536));
+        assert_eq!(parse_size("1"), Ok(1));
+    }
+
+    #[test]
+    fn parse_size_rejects_less_than_a_byte() {
+        for value in ["", "G", "0", "0.5", "0.0001K", "-1M", "big", "inf", "NaN"] {
+            assert!(parse_size(value).is_err(), "{} was accepted", value);
+        }
+    }
+
+    #[test]
+    fn plan_parts_splits_at_chapter_boundaries() {
+        let limits = SplitLimits {
+            max_duration: Some(100.0),
+            max_size: None,
+        };
+        assert_eq!(
+            plan_parts(&[60.0, 30.0, 20.0, 150.0, 10.0], &[], &limits),
+            vec![0..2, 2..3, 3..4, 4..5]
+        );
+        let limits = SplitLimits {
+            max_duration: None,
+            max_size: Some(10),
+        };
+        assert_eq!(plan_parts(&[1.0; 3], &[5, 5, 5], &limits), vec![0..2, 2..3]);
+        assert_eq!(
+            plan_parts(&[1.0; 3], &[5, 5, 5], &SplitLimits::default()),
+            vec![0..3]
+        );
+        assert!(plan_parts(&[], &[], &limits).is_empty());
+    }
+}
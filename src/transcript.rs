@@ -0,0 +1,195 @@
+// src/transcript.rs
+use clap::ValueEnum;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Subtitle/lyrics formats the transcript can be written in
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TranscriptFormat {
+    Srt,
+    Vtt,
+    Lrc,
+}
+
+impl TranscriptFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TranscriptFormat::Srt => "srt",
+            TranscriptFormat::Vtt => "vtt",
+            TranscriptFormat::Lrc => "lrc",
+        }
+    }
+}
+
+/// A piece of text and when it is spoken, in milliseconds
+#[derive(Clone, Debug)]
+pub struct Cue {
+    pub start: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+impl Cue {
+    pub fn offset(&self, by: f64) -> Cue {
+        Cue {
+            start: self.start + by,
+            end: self.end + by,
+            text: self.text.clone(),
+        }
+    }
+}
+
+/// Start and end (ms, relative to the chapter) of each joined paragraph, from the
+/// paragraph numbers and measured lengths in the order they were joined with `silence`
+/// ms between them
+pub fn paragraph_times(paragraph_lengths: &[(usize, f64)], silence: f64) -> Vec<(usize, f64, f64)> {
+    let mut times = Vec::new();
+    let mut time = 0.0;
+    for (i, (number, length)) in paragraph_lengths.iter().enumerate() {
+        if i > 0 {
+            time += silence;
+        }
+        times.push((*number, time, time + length));
+        time += length;
+    }
+    times
+}
+
+/// Sentence cues for one chapter, relative to its start. Paragraph numbers in
+/// `paragraph_lengths` are 1-based indexes into `texts`.
+///
+/// edge-tts only returns the audio, without word boundary events, so paragraphs are
+/// timed exactly and the sentences inside them are spread by their length.
+pub fn chapter_cues(
+    texts: &[String],
+    paragraph_lengths: &[(usize, f64)],
+    silence: f64,
+) -> Vec<Cue> {
+    let mut cues = Vec::new();
+    for (number, start, end) in paragraph_times(paragraph_lengths, silence) {
+        let Some(text) = number.checked_sub(1).and_then(|index| texts.get(index)) else {
+            continue;
+        };
+
+        let sentences = split_sentences(text);
+        let total_chars: usize = sentences.iter().map(|s| s.chars().count()).sum();
+        let mut sentence_start = start;
+        for sentence in sentences {
+            let share = sentence.chars().count() as f64 / total_chars.max(1) as f64;
+            let sentence_end = sentence_start + (end - start) * share;
+            cues.push(Cue {
+                start: sentence_start,
+                end: sentence_end,
+                text: sentence,
+            });
+            sentence_start = sentence_end;
+        }
+    }
+    cues
+}
+
+// Splits after ., ! and ? (and any closing quotes or brackets) followed by whitespace
+// and a word that doesn't start in lower case
+fn split_sentences(text: &str) -> Vec<String> {
+    let mut sentences = Vec::new();
+    let mut current = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        current.push(c);
+        if matches!(c, '.' | '!' | '?' | '…') {
+            while let Some(&next) = chars.peek() {
+                if matches!(next, '"' | '\'' | '”' | '’' | ')' | ']' | '.' | '!' | '?') {
+                    current.push(next);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            // "Hello!" she said. stays one sentence
+            let continues_lower = chars
+                .clone()
+                .find(|next| !next.is_whitespace())
+                .is_some_and(|next| next.is_lowercase());
+            if chars.peek().is_none_or(|next| next.is_whitespace()) && !continues_lower {
+                let sentence = current.trim().to_string();
+                if !sentence.is_empty() {
+                    sentences.push(sentence);
+                }
+                current.clear();
+            }
+        }
+    }
+    let rest = current.trim();
+    if !rest.is_empty() {
+        sentences.push(rest.to_string());
+    }
+    sentences
+}
+
+/// Writes the cues to `path` in the given format. LRC files also get the title and author.
+pub fn write_transcript(
+    path: &Path,
+    format: TranscriptFormat,
+    cues: &[Cue],
+    title: &str,
+    author: Option<&str>,
+) -> io::Result<()> {
+    let mut output = String::new();
+    match format {
+        TranscriptFormat::Srt => {
+            for (i, cue) in cues.iter().enumerate() {
+                output.push_str(&format!(
+                    "{}\n{} --> {}\n{}\n\n",
+                    i + 1,
+                    timestamp(cue.start, ','),
+                    timestamp(cue.end, ','),
+                    cue.text
+                ));
+            }
+        }
+        TranscriptFormat::Vtt => {
+            output.push_str("WEBVTT\n\n");
+            for cue in cues {
+                output.push_str(&format!(
+                    "{} --> {}\n{}\n\n",
+                    timestamp(cue.start, '.'),
+                    timestamp(cue.end, '.'),
+                    cue.text
+                ));
+            }
+        }
+        TranscriptFormat::Lrc => {
+            output.push_str(&format!("[ti:{}]\n", title));
+            if let Some(author) = author {
+                output.push_str(&format!("[ar:{}]\n", author));
+            }
+            for cue in cues {
+                // LRC uses minutes:seconds.hundredths
+                let hundredths = (cue.start / 10.0).round() as u64;
+                output.push_str(&format!(
+                    "[{:02}:{:02}.{:02}]{}\n",
+                    hundredths / 6000,
+                    hundredths / 100 % 60,
+                    hundredths % 100,
+                    cue.text
+                ));
+            }
+        }
+    }
+    fs::write(path, output)
+}
+
+// hh:mm:ss,mmm (SRT) or hh:mm:ss.mmm (WebVTT)
+fn timestamp(ms: f64, separator: char) -> String {
+    let ms = ms.max(0.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}{}{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        separator,
+        ms % 1000
+    )
+}
@@ -0,0 +1,128 @@
+// src/tts.rs
+use clap::ValueEnum;
+use edge_tts::{build_ssml, request_audio};
+use std::process::Command;
+use std::sync::Arc;
+
+/// Sample rate of every stream edge-tts is asked for
+pub const SAMPLE_RATE: u32 = 24000;
+
+/// Audio stream requested from edge-tts for each paragraph
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TtsFormat {
+    /// 96 kbps MP3, re-encoded unless the output is MP3 too
+    Mp3,
+    /// 16 bit PCM, so the output encoder is the only lossy step
+    Wav,
+    /// Ogg Opus, copied without re-encoding into Opus output
+    Opus,
+}
+
+impl TtsFormat {
+    /// Output format name understood by the edge-tts service
+    pub fn edge_format(&self) -> &'static str {
+        match self {
+            TtsFormat::Mp3 => "audio-24khz-96kbitrate-mono-mp3",
+            TtsFormat::Wav => "riff-24khz-16bit-mono-pcm",
+            TtsFormat::Opus => "ogg-24khz-16bit-mono-opus",
+        }
+    }
+
+    /// Extension of the paragraph files
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TtsFormat::Mp3 => "mp3",
+            TtsFormat::Wav => "wav",
+            TtsFormat::Opus => "ogg",
+        }
+    }
+
+    /// FFmpeg encoder that produces the same stream, used for silence between paragraphs
+    pub fn encoder_args(&self) -> Vec<&'static str> {
+        match self {
+            TtsFormat::Mp3 => vec!["-c:a", "libmp3lame", "-b:a", "96k"],
+            TtsFormat::Wav => vec!["-c:a", "pcm_s16le"],
+            TtsFormat::Opus => vec!["-c:a", "libopus"],
+        }
+    }
+
+    /// FFmpeg encoder name of the stream, to tell when it can be copied as is
+    pub fn codec(&self) -> &'static str {
+        match self {
+            TtsFormat::Mp3 => "libmp3lame",
+            TtsFormat::Wav => "pcm_s16le",
+            TtsFormat::Opus => "libopus",
+        }
+    }
+}
+
+/// Voice and speaking rate passed to the engine
+#[derive(Clone, Debug)]
+pub struct Voice {
+    /// Voice short name, e.g. en-US-BrianNeural
+    pub name: String,
+    /// "medium", "fast" or a relative rate such as "+10%"
+    pub rate: String,
+}
+
+/// A speech synthesis backend
+pub trait TtsEngine: Send + Sync {
+    /// Audio of one paragraph as a complete file in the given stream format
+    fn synthesize(&self, text: &str, voice: &Voice, format: TtsFormat) -> Result<Vec<u8>, String>;
+}
+
+/// Which engine to synthesize with
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EngineKind {
+    /// Microsoft Edge's online voices
+    Edge,
+    /// Silence as long as the text would take to read, for trying things out offline
+    Stub,
+}
+
+impl EngineKind {
+    pub fn engine(&self) -> Arc<dyn TtsEngine> {
+        match self {
+            EngineKind::Edge => Arc::new(EdgeTts),
+            EngineKind::Stub => Arc::new(StubEngine),
+        }
+    }
+}
+
+pub struct EdgeTts;
+
+impl TtsEngine for EdgeTts {
+    fn synthesize(&self, text: &str, voice: &Voice, format: TtsFormat) -> Result<Vec<u8>, String> {
+        let ssml = build_ssml(text, &voice.name, "medium", &voice.rate, "medium");
+        request_audio(&ssml, format.edge_format()).map_err(|e| e.to_string())
+    }
+}
+
+/// Makes silence with FFmpeg instead of calling a service
+pub struct StubEngine;
+
+// Roughly how fast the voices read at medium rate
+const STUB_CHARACTERS_PER_SECOND: f64 = 15.0;
+
+impl TtsEngine for StubEngine {
+    fn synthesize(&self, text: &str, _voice: &Voice, format: TtsFormat) -> Result<Vec<u8>, String> {
+        let duration = (text.chars().count() as f64 / STUB_CHARACTERS_PER_SECOND).max(0.5);
+        let container = match format {
+            TtsFormat::Mp3 => "mp3",
+            TtsFormat::Wav => "wav",
+            TtsFormat::Opus => "ogg",
+        };
+        let output = Command::new("ffmpeg")
+            .args(["-v", "error", "-f", "lavfi", "-i"])
+            .arg(format!("anullsrc=r={}:cl=mono", SAMPLE_RATE))
+            .args(["-t", &format!("{:.3}", duration)])
+            .args(format.encoder_args())
+            .args(["-f", container, "pipe:1"])
+            .output()
+            .map_err(|e| format!("Failed to execute ffmpeg: {}", e))?;
+        if !output.status.success() {
+            return Err(format!("ffmpeg failed with status: {}", output.status));
+        }
+        Ok(output.stdout)
+    }
+}
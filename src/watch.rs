@@ -0,0 +1,171 @@
+// src/watch.rs
+use crate::feed;
+use crate::naming;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The shared folders of the watch mode
+pub struct WatchFolders {
+    /// New books are dropped here
+    pub inbox: PathBuf,
+    /// Finished audiobooks are moved here
+    pub outbox: PathBuf,
+    /// Books that were converted are moved here
+    pub processed: PathBuf,
+    /// Books that failed are moved here, with a log
+    pub errors: PathBuf,
+}
+
+impl WatchFolders {
+    /// Where a book is made before it's moved to the outbox, so nobody picks up a
+    /// half-written file
+    pub fn work_dir(&self) -> PathBuf {
+        self.outbox.join(".work")
+    }
+
+    /// Creates the folders. None of the others may be inside the inbox, or the books
+    /// moved there would be picked up again.
+    pub fn create(&self) -> io::Result<()> {
+        for dir in [&self.inbox, &self.outbox, &self.processed, &self.errors] {
+            fs::create_dir_all(dir)?;
+        }
+        let inbox = fs::canonicalize(&self.inbox)?;
+        for dir in [&self.outbox, &self.processed, &self.errors] {
+            if fs::canonicalize(dir)?.starts_with(&inbox) {
+                return Err(io::Error::other(format!(
+                    "{} is inside the inbox {}",
+                    dir.display(),
+                    self.inbox.display()
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Remembers the size and modification time of the files in the inbox, so a book is
+/// only picked up once it has stopped changing (i.e. it's done copying)
+#[derive(Default)]
+pub struct Settling {
+    seen: HashMap<PathBuf, (u64, SystemTime)>,
+}
+
+impl Settling {
+    /// Whether none of the files changed since the last time they were looked at
+    pub fn is_settled(&mut self, paths: &[&Path]) -> bool {
+        let mut settled = true;
+        for path in paths {
+            let Ok(metadata) = fs::metadata(path) else {
+                return false;
+            };
+            let state = (metadata.len(), metadata.modified().unwrap_or(UNIX_EPOCH));
+            if self.seen.insert(path.to_path_buf(), state) != Some(state) {
+                settled = false;
+            }
+        }
+        settled
+    }
+
+    pub fn forget(&mut self, paths: &[&Path]) {
+        for path in paths {
+            self.seen.remove(*path);
+        }
+    }
+}
+
+/// Moves everything made in `work_dir` into `outbox`, keeping the folders of the name
+/// template. Returns the moved files and folders.
+pub fn deliver(work_dir: &Path, outbox: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut delivered = Vec::new();
+    move_contents(work_dir, outbox, &mut delivered)?;
+    Ok(delivered)
+}
+
+// Folders that already exist in the outbox (an author folder, say) are merged,
+// a file that exists gets a " (2)" name
+fn move_contents(from: &Path, to: &Path, moved: &mut Vec<PathBuf>) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let path = entry?.path();
+        let target = to.join(path.file_name().unwrap_or_default());
+        if path.is_dir() && target.is_dir() {
+            move_contents(&path, &target, moved)?;
+            continue;
+        }
+        let target = if target.exists() {
+            free_name(&target)
+        } else {
+            target
+        };
+        move_path(&path, &target)?;
+        moved.push(target);
+    }
+    Ok(())
+}
+
+/// Moves the book's files into `dir`
+pub fn archive(files: &[&Path], dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for file in files {
+        let target = dir.join(file.file_name().unwrap_or_default());
+        let target = if target.exists() {
+            free_name(&target)
+        } else {
+            target
+        };
+        move_path(file, &target)?;
+    }
+    Ok(())
+}
+
+/// Writes `<book>.log` to the errors folder saying why the book failed
+pub fn write_error_log(dir: &Path, files: &[&Path], error: &str) -> io::Result<PathBuf> {
+    let book = files.first().copied().unwrap_or(Path::new("book"));
+    let stem = book.file_stem().unwrap_or_default().to_string_lossy();
+    let log = naming::unique_path(&dir.join(stem.as_ref()), "log");
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0);
+    let mut text = format!("Failed: {}\n\n{}\n\nFiles:\n", feed::rfc2822(now), error);
+    for file in files {
+        text.push_str(&format!("  {}\n", file.display()));
+    }
+    fs::write(&log, text)?;
+    Ok(log)
+}
+
+// "name (2).ext" next to a path that's taken
+fn free_name(path: &Path) -> PathBuf {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let stem = match path.file_stem() {
+        Some(stem) if !extension.is_empty() && !path.is_dir() => path.with_file_name(stem),
+        _ => path.to_path_buf(),
+    };
+    naming::unique_path(&stem, &extension)
+}
+
+// A rename doesn't work across drives, the shared folder may well be on another one
+fn move_path(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    if from.is_dir() {
+        fs::create_dir_all(to)?;
+        for entry in fs::read_dir(from)? {
+            let path = entry?.path();
+            move_path(&path, &to.join(path.file_name().unwrap_or_default()))?;
+        }
+        fs::remove_dir(from)
+    } else {
+        fs::copy(from, to)?;
+        fs::remove_file(from)
+    }
+}